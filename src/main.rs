@@ -1,10 +1,11 @@
-use config::{Config, ConfigError, File};
+use config::{Config, ConfigError, File, FileFormat};
 use inquire::validator::Validation;
 use itertools::Itertools;
+use petgraph::algo::astar;
 use petgraph::graph::NodeIndex;
 use petgraph::unionfind::UnionFind;
 use petgraph::visit::EdgeRef;
-use petgraph::Graph;
+use petgraph::{Graph, Undirected};
 use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
@@ -15,7 +16,7 @@ use std::collections::HashSet;
 use std::fs;
 use std::hash::{Hash, Hasher};
 use std::io::{self, BufRead, Write};
-use strum::EnumCount;
+use strum::{EnumCount, IntoEnumIterator};
 use strum_macros::{EnumCount, EnumIter};
 
 // Struct for config settings
@@ -37,10 +38,872 @@ struct AppConfig {
     max_rooms: u32,
     min_containers: u32,
     max_containers: u32,
+    hallway_min_rooms: u32,
+    door_lock_chance: f64,
+    archway_chance: f64,
+    markov_names: bool,
+    markov_order: usize,
+    markov_min_length: usize,
+    markov_max_length: usize,
+    markov_allow_training_names: bool,
+    grammar_file: String,
+    town_cultures: bool,
+    map_width: u32,
+    ascii_maps: bool,
+    svg_width: u32,
+    svg_town_insets: bool,
+    png_width: u32,
+    terrain_scale: f64,
+    town_placement_radius: u32,
+    num_of_rivers: u32,
+    port_distance: u32,
+    river_cost: u32,
+    sea_cost: u32,
+    river_route_style: String,
+    sea_route_style: String,
+    capital: bool,
+    capital_size: String,
+    capital_scale: f64,
+    capital_dot_style: String,
+    waystation_distance: u32,
+    num_of_points_of_interest: u32,
+    trail_distance: u32,
+    trail_cost: u32,
+    trail_route_style: String,
+    dungeon_lock_chance: f64,
+    dungeon_loop_chance: f64,
+    locked_door_style: String,
+    #[serde(default = "default_races")]
+    races: Vec<RaceConfig>,
+    #[serde(default = "default_building_types")]
+    building_types: Vec<BuildingTypeConfig>,
+    #[serde(default)]
+    town_sizes: Vec<TownSizeConfig>,
+    #[serde(default)]
+    constraints: Vec<ConstraintConfig>,
+    #[serde(default)]
+    cultures: Vec<CultureConfig>,
+    #[serde(default = "default_biomes")]
+    biomes: Vec<BiomeConfig>,
+    #[serde(default = "default_road_types")]
+    road_types: Vec<RoadTypeConfig>,
+    #[serde(default = "default_travel_modes")]
+    travel_modes: Vec<TravelModeConfig>,
+    #[serde(default = "default_road_stops")]
+    road_stops: Vec<RoadStopConfig>,
+    #[serde(default = "default_points_of_interest")]
+    points_of_interest: Vec<PointOfInterestConfig>,
     input_dir: String,
     output_dir: String,
 }
 
+// Struct for a race's config settings
+#[derive(Debug, Deserialize, Clone)]
+struct RaceConfig {
+    name: String,
+    #[serde(default = "default_race_weight")]
+    weight: f32,
+    #[serde(default)]
+    variance: f32,
+    #[serde(default = "default_min_age")]
+    min_age: u32,
+    #[serde(default = "default_max_age")]
+    max_age: u32,
+    #[serde(default)]
+    culture: Option<String>,
+}
+
+fn default_race_weight() -> f32 {
+    1.0
+}
+
+fn default_min_age() -> u32 {
+    16
+}
+
+fn default_max_age() -> u32 {
+    80
+}
+
+// Races used when none are defined in the settings file
+fn default_races() -> Vec<RaceConfig> {
+    vec![
+        RaceConfig {
+            name: "Human".into(),
+            weight: 1.0,
+            variance: 0.0,
+            min_age: 16,
+            max_age: 80,
+            culture: None,
+        },
+        RaceConfig {
+            name: "Elf".into(),
+            weight: 1.0,
+            variance: 0.0,
+            min_age: 100,
+            max_age: 750,
+            culture: None,
+        },
+    ]
+}
+
+// Struct for a building type's config settings
+#[derive(Debug, Deserialize, Clone)]
+struct BuildingTypeConfig {
+    name: String,
+    #[serde(default = "default_building_weight")]
+    weight: f32,
+    #[serde(default)]
+    min_count: u32,
+    #[serde(default)]
+    max_count: Option<u32>,
+    #[serde(default)]
+    min_npcs: Option<u32>,
+    #[serde(default)]
+    max_npcs: Option<u32>,
+    #[serde(default)]
+    min_rooms: Option<u32>,
+    #[serde(default)]
+    max_rooms: Option<u32>,
+    #[serde(default = "default_container_types")]
+    containers: Vec<ContainerType>,
+    #[serde(default)]
+    name_template: Option<String>,
+    #[serde(default)]
+    family: bool,
+    #[serde(default = "default_district")]
+    district: String,
+    #[serde(default)]
+    port_district: Option<String>,
+    #[serde(default)]
+    dungeon: bool,
+    #[serde(default)]
+    room_types: Vec<RoomTypeConfig>,
+    #[serde(default)]
+    min_upper_floors: u32,
+    #[serde(default)]
+    max_upper_floors: u32,
+    #[serde(default)]
+    cellar_chance: f64,
+    #[serde(default)]
+    attic_chance: f64,
+}
+
+impl BuildingTypeConfig {
+    // Create a building type that uses the global settings for everything but its name
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            weight: 1.0,
+            min_count: 0,
+            max_count: None,
+            min_npcs: None,
+            max_npcs: None,
+            min_rooms: None,
+            max_rooms: None,
+            containers: default_container_types(),
+            name_template: None,
+            family: false,
+            district: default_district(),
+            port_district: None,
+            dungeon: false,
+            room_types: Vec::new(),
+            min_upper_floors: 0,
+            max_upper_floors: 0,
+            cellar_chance: 0.0,
+            attic_chance: 0.0,
+        }
+    }
+
+    // Key used for the building type's grammar rules, e.g. "town_hall"
+    fn key(&self) -> String {
+        self.name.to_lowercase().replace(' ', "_")
+    }
+}
+
+fn default_building_weight() -> f32 {
+    1.0
+}
+
+// Struct for a room type's config settings. Room types are picked for rooms with the same role, and decide
+// which containers are found in the room and how likely NPCs are to be there
+#[derive(Debug, Deserialize, Clone)]
+struct RoomTypeConfig {
+    name: String,
+    #[serde(default)]
+    role: RoomRole,
+    #[serde(default = "default_room_type_weight")]
+    weight: f32,
+    #[serde(default)]
+    min_count: u32,
+    #[serde(default)]
+    max_count: Option<u32>,
+    #[serde(default)]
+    containers: Option<Vec<ContainerType>>,
+    #[serde(default)]
+    min_containers: Option<u32>,
+    #[serde(default)]
+    max_containers: Option<u32>,
+    #[serde(default = "default_room_type_weight")]
+    npc_weight: f32,
+    #[serde(default)]
+    floors: Vec<FloorKind>,
+}
+
+impl RoomTypeConfig {
+    // Create a room type that uses its building type's containers and the global container range
+    fn new(name: &str, role: RoomRole) -> Self {
+        Self {
+            name: name.into(),
+            role,
+            weight: 1.0,
+            min_count: 0,
+            max_count: None,
+            containers: None,
+            min_containers: None,
+            max_containers: None,
+            npc_weight: 1.0,
+            floors: Vec::new(),
+        }
+    }
+
+    // Whether the room type can be found on a floor, any floor if no floors are set
+    fn allows(&self, floor: FloorKind) -> bool {
+        self.floors.is_empty() || self.floors.contains(&floor)
+    }
+}
+
+fn default_room_type_weight() -> f32 {
+    1.0
+}
+
+fn default_district() -> String {
+    "Residential".into()
+}
+
+fn default_container_types() -> Vec<ContainerType> {
+    ContainerType::iter().collect()
+}
+
+// Building types used when none are defined in the settings file
+fn default_building_types() -> Vec<BuildingTypeConfig> {
+    vec![
+        BuildingTypeConfig {
+            min_npcs: Some(2),
+            max_npcs: Some(2),
+            family: true,
+            room_types: vec![
+                RoomTypeConfig {
+                    npc_weight: 2.0,
+                    ..RoomTypeConfig::new("Hearth", RoomRole::Entrance)
+                },
+                RoomTypeConfig {
+                    min_count: 1,
+                    containers: Some(vec![ContainerType::Chest]),
+                    floors: vec![FloorKind::Ground, FloorKind::Upper],
+                    ..RoomTypeConfig::new("Bedroom", RoomRole::Chamber)
+                },
+            ],
+            max_upper_floors: 1,
+            cellar_chance: 0.2,
+            attic_chance: 0.3,
+            ..BuildingTypeConfig::new("Residence")
+        },
+        BuildingTypeConfig {
+            min_npcs: Some(1),
+            max_npcs: Some(1),
+            family: true,
+            district: "Market".into(),
+            room_types: vec![
+                RoomTypeConfig {
+                    npc_weight: 3.0,
+                    ..RoomTypeConfig::new("Shop Floor", RoomRole::Entrance)
+                },
+                RoomTypeConfig {
+                    min_count: 1,
+                    max_count: Some(1),
+                    containers: Some(vec![ContainerType::Barrel, ContainerType::Crate]),
+                    npc_weight: 0.2,
+                    ..RoomTypeConfig::new("Storeroom", RoomRole::Chamber)
+                },
+                RoomTypeConfig::new("Living Quarters", RoomRole::Chamber),
+            ],
+            max_upper_floors: 1,
+            cellar_chance: 0.3,
+            ..BuildingTypeConfig::new("Shop")
+        },
+        BuildingTypeConfig {
+            district: "Market".into(),
+            room_types: vec![
+                RoomTypeConfig {
+                    containers: Some(vec![ContainerType::Barrel]),
+                    npc_weight: 4.0,
+                    ..RoomTypeConfig::new("Common Room", RoomRole::Entrance)
+                },
+                RoomTypeConfig {
+                    min_count: 1,
+                    max_count: Some(1),
+                    containers: Some(vec![ContainerType::Barrel, ContainerType::Crate]),
+                    npc_weight: 2.0,
+                    floors: vec![FloorKind::Ground],
+                    ..RoomTypeConfig::new("Kitchen", RoomRole::Chamber)
+                },
+                RoomTypeConfig {
+                    max_count: Some(1),
+                    containers: Some(vec![ContainerType::Barrel, ContainerType::Crate]),
+                    min_containers: Some(2),
+                    max_containers: Some(5),
+                    npc_weight: 0.0,
+                    floors: vec![FloorKind::Cellar],
+                    ..RoomTypeConfig::new("Cellar", RoomRole::Chamber)
+                },
+                RoomTypeConfig {
+                    weight: 2.0,
+                    containers: Some(vec![ContainerType::Chest]),
+                    npc_weight: 0.5,
+                    floors: vec![FloorKind::Ground, FloorKind::Upper],
+                    ..RoomTypeConfig::new("Guest Room", RoomRole::Chamber)
+                },
+            ],
+            min_upper_floors: 1,
+            max_upper_floors: 2,
+            cellar_chance: 0.8,
+            ..BuildingTypeConfig::new("Tavern")
+        },
+        BuildingTypeConfig {
+            district: "Temple Quarter".into(),
+            room_types: vec![
+                RoomTypeConfig {
+                    containers: Some(Vec::new()),
+                    npc_weight: 3.0,
+                    ..RoomTypeConfig::new("Nave", RoomRole::Entrance)
+                },
+                RoomTypeConfig {
+                    min_count: 1,
+                    max_count: Some(1),
+                    containers: Some(vec![ContainerType::Chest]),
+                    floors: vec![FloorKind::Ground, FloorKind::Upper],
+                    ..RoomTypeConfig::new("Vestry", RoomRole::Chamber)
+                },
+                RoomTypeConfig {
+                    max_count: Some(1),
+                    containers: Some(vec![ContainerType::Chest]),
+                    npc_weight: 0.2,
+                    floors: vec![FloorKind::Cellar],
+                    ..RoomTypeConfig::new("Crypt", RoomRole::Chamber)
+                },
+                RoomTypeConfig::new("Cell", RoomRole::Chamber),
+            ],
+            cellar_chance: 0.5,
+            ..BuildingTypeConfig::new("Temple")
+        },
+    ]
+}
+
+// Struct for a town size's config settings
+#[derive(Debug, Deserialize, Clone)]
+struct TownSizeConfig {
+    name: String,
+    #[serde(default = "default_town_size_weight")]
+    weight: f32,
+    #[serde(default)]
+    max_count: Option<u32>,
+    min_buildings: u32,
+    max_buildings: u32,
+    #[serde(default)]
+    services: HashMap<String, u32>,
+    #[serde(default = "default_population_multiplier")]
+    population_multiplier: u32,
+    #[serde(default)]
+    dot_style: String,
+    #[serde(default)]
+    extra_floors: u32,
+}
+
+impl TownSizeConfig {
+    // Create a town size with no services
+    fn new(name: &str, weight: f32, min_buildings: u32, max_buildings: u32) -> Self {
+        Self {
+            name: name.into(),
+            weight,
+            max_count: None,
+            min_buildings,
+            max_buildings,
+            services: HashMap::new(),
+            population_multiplier: default_population_multiplier(),
+            dot_style: String::new(),
+            extra_floors: 0,
+        }
+    }
+
+    // Get the number of buildings of a type the town size guarantees
+    fn service_count(&self, building_type: &str) -> u32 {
+        self.services
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(building_type))
+            .map(|(_, &count)| count)
+            .sum()
+    }
+}
+
+fn default_town_size_weight() -> f32 {
+    1.0
+}
+
+fn default_population_multiplier() -> u32 {
+    10
+}
+
+// Struct for a constraint on the buildings of a town
+#[derive(Debug, Deserialize, Clone)]
+struct ConstraintConfig {
+    building_type: String,
+    #[serde(default)]
+    min: Option<u32>,
+    #[serde(default)]
+    max: Option<u32>,
+    #[serde(default)]
+    per_buildings: Option<u32>,
+    #[serde(default)]
+    requires: Option<String>,
+    #[serde(default)]
+    town_sizes: Vec<String>,
+}
+
+impl ConstraintConfig {
+    // Check if the constraint applies to towns of a size, applies to all sizes if none are given
+    fn applies_to(&self, town_size: &TownSizeConfig) -> bool {
+        self.town_sizes.is_empty()
+            || self
+                .town_sizes
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&town_size.name))
+    }
+
+    // Scale a min or max by the number of buildings, e.g. "1 per 10 buildings" allows 3 in a town of 25
+    fn scale(&self, value: u32, number_of_buildings: u32) -> u32 {
+        match self.per_buildings {
+            Some(per_buildings) if per_buildings > 0 => {
+                value * number_of_buildings.div_ceil(per_buildings)
+            }
+            _ => value,
+        }
+    }
+}
+
+// Struct for a naming culture's config settings
+#[derive(Debug, Deserialize, Clone)]
+struct CultureConfig {
+    name: String,
+    #[serde(default)]
+    races: Vec<String>,
+    #[serde(default)]
+    lists: Option<String>,
+    #[serde(default)]
+    onsets: Vec<String>,
+    #[serde(default)]
+    vowels: Vec<String>,
+    #[serde(default)]
+    codas: Vec<String>,
+    #[serde(default = "default_min_syllables")]
+    min_syllables: u32,
+    #[serde(default = "default_max_syllables")]
+    max_syllables: u32,
+}
+
+fn default_min_syllables() -> u32 {
+    2
+}
+
+fn default_max_syllables() -> u32 {
+    3
+}
+
+// Struct for a biome's config settings, a point's biome is the first one whose elevation and moisture
+// ranges contain it
+#[derive(Debug, Deserialize, Clone)]
+struct BiomeConfig {
+    name: String,
+    #[serde(default)]
+    min_elevation: f64,
+    #[serde(default = "default_max_range")]
+    max_elevation: f64,
+    #[serde(default)]
+    min_moisture: f64,
+    #[serde(default = "default_max_range")]
+    max_moisture: f64,
+    #[serde(default = "default_suitability")]
+    suitability: f64,
+    #[serde(default)]
+    water: bool,
+    colour: [u8; 3],
+    #[serde(default)]
+    building_weights: HashMap<String, f32>,
+}
+
+impl BiomeConfig {
+    fn new(name: &str, elevation: (f64, f64), moisture: (f64, f64), colour: [u8; 3]) -> Self {
+        Self {
+            name: name.into(),
+            min_elevation: elevation.0,
+            max_elevation: elevation.1,
+            min_moisture: moisture.0,
+            max_moisture: moisture.1,
+            suitability: default_suitability(),
+            water: false,
+            colour,
+            building_weights: HashMap::new(),
+        }
+    }
+
+    fn contains(&self, elevation: f64, moisture: f64) -> bool {
+        (self.min_elevation..=self.max_elevation).contains(&elevation)
+            && (self.min_moisture..=self.max_moisture).contains(&moisture)
+    }
+
+    // Multiplier for a building type's weight in towns of this biome
+    fn building_weight(&self, building_type: &str) -> f32 {
+        self.building_weights
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(building_type))
+            .map(|(_, weight)| *weight)
+            .unwrap_or(1.0)
+    }
+}
+
+fn default_max_range() -> f64 {
+    1.0
+}
+
+fn default_suitability() -> f64 {
+    1.0
+}
+
+fn default_biomes() -> Vec<BiomeConfig> {
+    let biome = |name, elevation, moisture, colour, suitability, weights: &[(&str, f32)]| {
+        let mut biome = BiomeConfig::new(name, elevation, moisture, colour);
+        biome.suitability = suitability;
+        biome.building_weights = weights
+            .iter()
+            .map(|(name, weight)| (name.to_string(), *weight))
+            .collect();
+        biome
+    };
+
+    let mut sea = biome("Sea", (0.0, 0.3), (0.0, 1.0), [70, 110, 170], 0.0, &[]);
+    sea.water = true;
+
+    vec![
+        sea,
+        biome("Coast", (0.3, 0.36), (0.0, 1.0), [222, 208, 150], 1.5, &[]),
+        biome(
+            "Swamp",
+            (0.36, 0.5),
+            (0.7, 1.0),
+            [96, 112, 80],
+            0.3,
+            &[("Temple", 1.5)],
+        ),
+        biome(
+            "Forest",
+            (0.36, 0.65),
+            (0.5, 1.0),
+            [78, 122, 64],
+            0.7,
+            &[("Stable", 0.5)],
+        ),
+        biome(
+            "Plains",
+            (0.36, 0.65),
+            (0.0, 0.5),
+            [176, 192, 112],
+            1.0,
+            &[("Stable", 2.0)],
+        ),
+        biome(
+            "Hills",
+            (0.65, 0.8),
+            (0.0, 1.0),
+            [150, 146, 100],
+            0.6,
+            &[("Blacksmith", 2.0)],
+        ),
+        biome(
+            "Mountains",
+            (0.8, 1.0),
+            (0.0, 1.0),
+            [136, 126, 118],
+            0.1,
+            &[("Blacksmith", 3.0)],
+        ),
+    ]
+}
+
+// Struct for a road type's config settings, a route's type is the first one for its kind of route whose
+// conditions it meets
+#[derive(Debug, Deserialize, Clone)]
+struct RoadTypeConfig {
+    name: String,
+    #[serde(default)]
+    route: RouteKind,
+    #[serde(default = "default_road_speed")]
+    speed: f64,
+    #[serde(default)]
+    fixed_speed: Option<f64>,
+    #[serde(default)]
+    danger: f32,
+    #[serde(default)]
+    toll: u32,
+    #[serde(default)]
+    min_elevation: Option<f64>,
+    #[serde(default)]
+    min_buildings: Option<u32>,
+}
+
+impl RoadTypeConfig {
+    fn new(name: &str, route: RouteKind, speed: f64, danger: f32, toll: u32) -> Self {
+        Self {
+            name: name.into(),
+            route,
+            speed,
+            fixed_speed: None,
+            danger,
+            toll,
+            min_elevation: None,
+            min_buildings: None,
+        }
+    }
+}
+
+fn default_road_speed() -> f64 {
+    1.0
+}
+
+fn default_road_types() -> Vec<RoadTypeConfig> {
+    let mut mountain_pass = RoadTypeConfig::new("Mountain Pass", RouteKind::Road, 0.5, 0.4, 0);
+    mountain_pass.min_elevation = Some(0.65);
+
+    let mut highway = RoadTypeConfig::new("Highway", RouteKind::Road, 1.25, 0.05, 2);
+    highway.min_buildings = Some(15);
+
+    let mut ferry = RoadTypeConfig::new("Ferry", RouteKind::River, 1.0, 0.1, 5);
+    ferry.fixed_speed = Some(6.0);
+
+    vec![
+        mountain_pass,
+        highway,
+        RoadTypeConfig::new("Dirt Track", RouteKind::Road, 0.9, 0.2, 0),
+        ferry,
+        RoadTypeConfig::new("Sea Lane", RouteKind::Sea, 1.0, 0.25, 0),
+        RoadTypeConfig::new("Trail", RouteKind::Trail, 0.7, 0.3, 0),
+    ]
+}
+
+// Struct for a travel mode's config settings
+#[derive(Debug, Deserialize, Clone)]
+struct TravelModeConfig {
+    name: String,
+    speed: f64,
+    #[serde(default)]
+    cost: f64,
+    #[serde(default)]
+    road_types: Vec<String>,
+}
+
+impl TravelModeConfig {
+    fn can_use(&self, road_type: &str) -> bool {
+        self.road_types.is_empty()
+            || self
+                .road_types
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(road_type))
+    }
+}
+
+fn default_travel_modes() -> Vec<TravelModeConfig> {
+    let mode = |name: &str, speed, cost, road_types: &[&str]| TravelModeConfig {
+        name: name.into(),
+        speed,
+        cost,
+        road_types: road_types.iter().map(|name| name.to_string()).collect(),
+    };
+
+    vec![
+        mode(
+            "Walking",
+            5.0,
+            0.0,
+            &["Highway", "Dirt Track", "Mountain Pass", "Ferry", "Trail"],
+        ),
+        mode(
+            "Horse",
+            12.0,
+            0.5,
+            &["Highway", "Dirt Track", "Mountain Pass", "Ferry", "Trail"],
+        ),
+        mode("Cart", 4.0, 0.2, &["Highway", "Dirt Track", "Ferry"]),
+        mode("Ship", 8.0, 1.0, &["Ferry", "Sea Lane"]),
+    ]
+}
+
+// Struct for a road stop's config settings, the buildings every stop of its kind has
+#[derive(Debug, Deserialize, Clone)]
+struct RoadStopConfig {
+    kind: TownKind,
+    #[serde(default)]
+    buildings: HashMap<String, u32>,
+    #[serde(default)]
+    dot_style: String,
+}
+
+impl RoadStopConfig {
+    fn new(kind: TownKind, buildings: &[(&str, u32)], dot_style: &str) -> Self {
+        Self {
+            kind,
+            buildings: buildings
+                .iter()
+                .map(|&(name, count)| (name.to_string(), count))
+                .collect(),
+            dot_style: dot_style.into(),
+        }
+    }
+
+    // Get the number of buildings of a type the stop has
+    fn building_count(&self, building_type: &str) -> u32 {
+        self.buildings
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(building_type))
+            .map(|(_, &count)| count)
+            .sum()
+    }
+}
+
+// Struct for a point of interest's config settings. Its rooms, inhabitants and containers are generated like
+// a building's, using the point of interest's kind as the building type
+#[derive(Debug, Deserialize, Clone)]
+struct PointOfInterestConfig {
+    kind: TownKind,
+    #[serde(default = "default_point_of_interest_weight")]
+    weight: f32,
+    #[serde(default)]
+    biomes: Vec<String>,
+    #[serde(default)]
+    min_rooms: Option<u32>,
+    #[serde(default)]
+    max_rooms: Option<u32>,
+    #[serde(default)]
+    min_npcs: Option<u32>,
+    #[serde(default)]
+    max_npcs: Option<u32>,
+    #[serde(default = "default_container_types")]
+    containers: Vec<ContainerType>,
+    #[serde(default)]
+    dungeon: bool,
+    #[serde(default)]
+    room_types: Vec<RoomTypeConfig>,
+    #[serde(default)]
+    dot_style: String,
+}
+
+impl PointOfInterestConfig {
+    fn new(kind: TownKind, rooms: (u32, u32), npcs: (u32, u32), biomes: &[&str]) -> Self {
+        Self {
+            kind,
+            weight: default_point_of_interest_weight(),
+            biomes: biomes.iter().map(|biome| biome.to_string()).collect(),
+            min_rooms: Some(rooms.0),
+            max_rooms: Some(rooms.1),
+            min_npcs: Some(npcs.0),
+            max_npcs: Some(npcs.1),
+            containers: default_container_types(),
+            dungeon: kind == TownKind::Dungeon,
+            room_types: Vec::new(),
+            dot_style: "shape=triangle, fontsize=10".into(),
+        }
+    }
+
+    // Whether the point of interest can be found in a biome, any land if no biomes are set
+    fn allows(&self, biome: &BiomeConfig) -> bool {
+        self.biomes.is_empty()
+            || self
+                .biomes
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&biome.name))
+    }
+
+    // The building type its rooms are generated from
+    fn building_type(&self) -> BuildingTypeConfig {
+        BuildingTypeConfig {
+            min_rooms: self.min_rooms,
+            max_rooms: self.max_rooms,
+            min_npcs: self.min_npcs,
+            max_npcs: self.max_npcs,
+            containers: self.containers.clone(),
+            district: self.kind.name().into(),
+            dungeon: self.dungeon,
+            room_types: self.room_types.clone(),
+            ..BuildingTypeConfig::new(self.kind.name())
+        }
+    }
+}
+
+fn default_point_of_interest_weight() -> f32 {
+    1.0
+}
+
+fn default_points_of_interest() -> Vec<PointOfInterestConfig> {
+    vec![
+        PointOfInterestConfig::new(TownKind::Ruins, (2, 5), (0, 2), &[]),
+        PointOfInterestConfig::new(
+            TownKind::Cave,
+            (1, 4),
+            (0, 3),
+            &["Forest", "Hills", "Mountains"],
+        ),
+        PointOfInterestConfig {
+            room_types: vec![RoomTypeConfig::new("Altar", RoomRole::Entrance)],
+            ..PointOfInterestConfig::new(TownKind::Shrine, (1, 2), (0, 1), &[])
+        },
+        PointOfInterestConfig {
+            room_types: vec![
+                RoomTypeConfig {
+                    npc_weight: 3.0,
+                    ..RoomTypeConfig::new("Campfire", RoomRole::Entrance)
+                },
+                RoomTypeConfig {
+                    max_count: Some(1),
+                    containers: Some(vec![ContainerType::Chest, ContainerType::Crate]),
+                    min_containers: Some(1),
+                    max_containers: Some(3),
+                    npc_weight: 0.5,
+                    ..RoomTypeConfig::new("Stash", RoomRole::Chamber)
+                },
+                RoomTypeConfig::new("Tent", RoomRole::Chamber),
+            ],
+            ..PointOfInterestConfig::new(
+                TownKind::BanditCamp,
+                (1, 3),
+                (3, 8),
+                &["Forest", "Hills", "Swamp"],
+            )
+        },
+        PointOfInterestConfig::new(TownKind::Dungeon, (6, 12), (2, 6), &[]),
+    ]
+}
+
+fn default_road_stops() -> Vec<RoadStopConfig> {
+    vec![
+        RoadStopConfig::new(TownKind::Crossroads, &[], "shape=diamond, fontsize=10"),
+        RoadStopConfig::new(
+            TownKind::Waystation,
+            &[("Tavern", 1), ("Stable", 1)],
+            "shape=house, fontsize=10",
+        ),
+        RoadStopConfig::new(TownKind::Bridge, &[], "shape=box, fontsize=10"),
+        RoadStopConfig::new(TownKind::Ford, &[], "shape=plaintext, fontsize=10"),
+    ]
+}
+
 impl AppConfig {
     fn load(filename: &str) -> Result<Self, ConfigError> {
         print!("Loading settings from file: \"{}\"... ", filename);
@@ -62,12 +925,93 @@ impl AppConfig {
             .set_default("max_rooms", 6)?
             .set_default("min_containers", 0)?
             .set_default("max_containers", 4)?
+            .set_default("hallway_min_rooms", 4)?
+            .set_default("door_lock_chance", 0.1)?
+            .set_default("archway_chance", 0.3)?
+            .set_default("markov_names", false)?
+            .set_default("markov_order", 2)?
+            .set_default("markov_min_length", 3)?
+            .set_default("markov_max_length", 10)?
+            .set_default("markov_allow_training_names", false)?
+            .set_default("grammar_file", "grammar.toml")?
+            .set_default("town_cultures", false)?
+            .set_default("map_width", 100)?
+            .set_default("ascii_maps", false)?
+            .set_default("svg_width", 1200)?
+            .set_default("svg_town_insets", true)?
+            .set_default("png_width", 1600)?
+            .set_default("terrain_scale", 80.0)?
+            .set_default("town_placement_radius", 10)?
+            .set_default("num_of_rivers", 4)?
+            .set_default("port_distance", 12)?
+            .set_default("river_cost", 2)?
+            .set_default("sea_cost", 3)?
+            .set_default("river_route_style", "style=dashed, color=steelblue")?
+            .set_default("sea_route_style", "style=dashed, color=navy")?
+            .set_default("capital", false)?
+            .set_default("capital_size", "Capital")?
+            .set_default("capital_scale", 1.0)?
+            .set_default("capital_dot_style", "peripheries=2")?
+            .set_default("waystation_distance", 20)?
+            .set_default("num_of_points_of_interest", 8)?
+            .set_default("trail_distance", 30)?
+            .set_default("trail_cost", 1)?
+            .set_default("trail_route_style", "style=dotted")?
+            .set_default("dungeon_lock_chance", 0.2)?
+            .set_default("dungeon_loop_chance", 0.1)?
+            .set_default("locked_door_style", "style=bold, color=firebrick")?
             .set_default("input_dir", "input")?
             .set_default("output_dir", "output")?
             .add_source(File::with_name(filename).required(false))
             .build()?;
 
-        file_contents.try_deserialize::<AppConfig>()
+        let mut config = file_contents.try_deserialize::<AppConfig>()?;
+
+        if config.races.is_empty() {
+            config.races = default_races();
+        }
+        if config.building_types.is_empty() {
+            config.building_types = default_building_types();
+        }
+        if config.biomes.is_empty() {
+            config.biomes = default_biomes();
+        }
+        if config.road_types.is_empty() {
+            config.road_types = default_road_types();
+        }
+        if config.travel_modes.is_empty() {
+            config.travel_modes = default_travel_modes();
+        }
+        // Dungeon rooms are named after their kind, so room types don't apply to them
+        let dungeon_room_types = config
+            .building_types
+            .iter()
+            .filter(|type_config| type_config.dungeon && !type_config.room_types.is_empty())
+            .map(|type_config| type_config.name.clone())
+            .chain(
+                config
+                    .points_of_interest
+                    .iter()
+                    .filter(|place| place.dungeon && !place.room_types.is_empty())
+                    .map(|place| place.kind.name().to_string()),
+            );
+        for name in dungeon_room_types {
+            eprintln!(
+                "Ignoring the room types of dungeon \"{}\", dungeon rooms are named after their kind",
+                name
+            );
+        }
+        // Without town sizes every town uses the global building range, as before town sizes existed
+        if config.town_sizes.is_empty() {
+            config.town_sizes = vec![TownSizeConfig::new(
+                "Town",
+                1.0,
+                config.min_buildings,
+                config.max_buildings,
+            )];
+        }
+
+        Ok(config)
     }
 }
 
@@ -79,6 +1023,121 @@ struct World {
     rooms: HashMap<u32, Room>,
     npcs: HashMap<u32, Npc>,
     containers: HashMap<u32, Container>,
+    #[serde(default)]
+    roads: Vec<Road>,
+    #[serde(default)]
+    terrain: Terrain,
+    #[serde(default)]
+    network: NetworkMetrics,
+    #[serde(default)]
+    dungeons: HashMap<u32, Dungeon>,
+}
+
+// Struct for the metrics of the whole road network, distances are along the roads. The capital is the ID of
+// the capital town if there is one
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct NetworkMetrics {
+    diameter: u32,
+    average_path_length: f64,
+    capital: Option<u32>,
+}
+
+// Struct for the centrality of a town in the road network. Betweenness is the share of shortest paths
+// between other towns that pass through it, closeness is one over the average distance to the towns it
+// can reach, and eccentricity is the distance to the furthest of them
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct TownMetrics {
+    degree: u32,
+    betweenness: f64,
+    closeness: f64,
+    eccentricity: u32,
+}
+
+// Struct for the world's terrain. Elevation and moisture are noise generated from the seed, so only the
+// seed and offset need to be saved to recreate it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Terrain {
+    seed: u64,
+    scale: f64,
+    offset: (f64, f64),
+    #[serde(default)]
+    rivers: Vec<River>,
+    #[serde(default)]
+    lakes: Vec<Lake>,
+}
+
+// Struct for representing a river, the points run downhill from its source
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct River {
+    points: Vec<(u32, u32)>,
+}
+
+// Struct for representing a lake, where a river ends in a hollow
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Lake {
+    centre: (u32, u32),
+    radius: u32,
+}
+
+impl Lake {
+    fn contains(&self, x: f64, y: f64) -> bool {
+        let (dx, dy) = (x - self.centre.0 as f64, y - self.centre.1 as f64);
+        dx * dx + dy * dy <= (self.radius * self.radius) as f64
+    }
+}
+
+// Placeholder terrain for worlds saved before terrain was generated, so they can still be loaded
+impl Default for Terrain {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            scale: 1.0,
+            offset: (0.0, 0.0),
+            rivers: Vec::new(),
+            lakes: Vec::new(),
+        }
+    }
+}
+
+impl Terrain {
+    fn elevation(&self, x: f64, y: f64) -> f64 {
+        fractal_noise(
+            self.seed,
+            (x + self.offset.0) / self.scale,
+            (y + self.offset.1) / self.scale,
+        )
+    }
+
+    fn moisture(&self, x: f64, y: f64) -> f64 {
+        fractal_noise(
+            self.seed ^ 0x5bd1e995,
+            (x + self.offset.0) / self.scale,
+            (y + self.offset.1) / self.scale,
+        )
+    }
+
+    fn biome<'a>(&self, settings: &'a AppConfig, x: f64, y: f64) -> &'a BiomeConfig {
+        let (elevation, moisture) = (self.elevation(x, y), self.moisture(x, y));
+
+        settings
+            .biomes
+            .iter()
+            .find(|biome| biome.contains(elevation, moisture))
+            .unwrap_or(&settings.biomes[settings.biomes.len() - 1])
+    }
+
+    fn is_water(&self, settings: &AppConfig, x: f64, y: f64) -> bool {
+        self.lakes.iter().any(|lake| lake.contains(x, y)) || self.biome(settings, x, y).water
+    }
+}
+
+// Struct for representing a road between two towns, used to save the graph's edges in the world
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Road {
+    from: u32,
+    to: u32,
+    #[serde(flatten)]
+    journey_info: JourneyInfo,
 }
 
 // Struct for representing a town
@@ -86,57 +1145,303 @@ struct World {
 struct Town {
     id: u32,
     name: String,
+    #[serde(default)]
+    culture: Option<String>,
+    #[serde(default)]
+    size: TownSize,
+    #[serde(default)]
+    population: u32,
     coords: (u32, u32),
+    #[serde(default)]
+    biome: Biome,
+    #[serde(default)]
+    port: bool,
+    #[serde(default)]
+    kind: TownKind,
+    #[serde(default)]
+    capital: bool,
+    #[serde(default)]
+    metrics: TownMetrics,
     number_of_buildings: u32,
     buildings: Vec<Building>,
+    #[serde(default)]
+    layout: TownLayout,
+}
+
+// Enum for the kinds of place in the road network, towns and the stops along the roads between them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, EnumIter)]
+enum TownKind {
+    #[default]
+    Town,
+    Crossroads,
+    Waystation,
+    Bridge,
+    Ford,
+    Ruins,
+    Cave,
+    Shrine,
+    BanditCamp,
+    Dungeon,
+}
+
+impl TownKind {
+    fn name(&self) -> &'static str {
+        match self {
+            TownKind::Town => "Town",
+            TownKind::Crossroads => "Crossroads",
+            TownKind::Waystation => "Waystation",
+            TownKind::Bridge => "Bridge",
+            TownKind::Ford => "Ford",
+            TownKind::Ruins => "Ruins",
+            TownKind::Cave => "Cave",
+            TownKind::Shrine => "Shrine",
+            TownKind::BanditCamp => "Bandit Camp",
+            TownKind::Dungeon => "Dungeon",
+        }
+    }
+
+    // Key used for the kind's grammar rule, e.g. "bandit_camp"
+    fn key(&self) -> String {
+        self.name().to_lowercase().replace(' ', "_")
+    }
+
+    fn is_point_of_interest(&self) -> bool {
+        matches!(
+            self,
+            TownKind::Ruins
+                | TownKind::Cave
+                | TownKind::Shrine
+                | TownKind::BanditCamp
+                | TownKind::Dungeon
+        )
+    }
+}
+
+// Struct for a biome, biomes are defined in the settings file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(transparent)]
+struct Biome(String);
+
+// Struct for town size, town sizes are defined in the settings file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(transparent)]
+struct TownSize(String);
+
+// Struct for representing the layout of a town, a central square with streets leading out of it.
+// Coordinates are in metres from the top left corner of the town
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct TownLayout {
+    width: u32,
+    height: u32,
+    square: Square,
+    streets: Vec<Street>,
+    districts: Vec<District>,
+}
+
+// Struct for representing a town's central square
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct Square {
+    name: String,
+    plot: Plot,
+}
+
+// Struct for representing a street, runs in a straight line out from the square
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Street {
+    name: String,
+    start: (u32, u32),
+    end: (u32, u32),
+    width: u32,
+    districts: Vec<String>,
+}
+
+// Struct for representing a district of a town
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct District {
+    name: String,
+    streets: Vec<String>,
+    buildings: Vec<u32>,
+}
+
+// Struct for representing a rectangular plot of land
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct Plot {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
 }
 
 // Struct for representing a raw town (containing just a town's name) when importing a DOT file
 #[derive(Debug, Clone)]
 struct TownRaw {
     name: String,
+    kind: TownKind,
 }
 
-// Struct for storing distance between towns and cost in the edges
-#[derive(Debug, Clone)]
+// Struct for storing distance between towns and cost in the edges, along with the road type and the time
+// and cost of the journey for each travel mode that can use it
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct JourneyInfo {
     distance: u32,
     cost: u32,
+    #[serde(default)]
+    route: RouteKind,
+    #[serde(default)]
+    road_type: String,
+    #[serde(default)]
+    danger: f32,
+    #[serde(default)]
+    toll: u32,
+    #[serde(default)]
+    travel: Vec<TravelInfo>,
+}
+
+// Struct for the time in hours and cost in gold of a journey by one travel mode
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct TravelInfo {
+    mode: String,
+    hours: f64,
+    cost: u32,
 }
 
 impl JourneyInfo {
+    fn new(distance: u32, cost: u32, route: RouteKind) -> Self {
+        Self {
+            distance,
+            cost,
+            route,
+            road_type: String::new(),
+            danger: 0.0,
+            toll: 0,
+            travel: Vec::new(),
+        }
+    }
+
+    // Only the first line of the label is needed, the road type is read from the second line if there is one
     fn from_label(label: &str) -> Option<Self> {
-        let parts: Vec<&str> = label.split("/").map(|s| s.trim()).collect();
+        let mut lines = label.split("\\n");
+        let parts: Vec<&str> = lines.next()?.split("/").map(|s| s.trim()).collect();
         if parts.len() == 2 {
             let distance = parts[0].split_whitespace().next()?.parse().ok()?;
             let cost = parts[1].split_whitespace().next()?.parse().ok()?;
-            Some(Self { distance, cost })
+            let route = RouteKind::iter()
+                .filter(|route| route != &RouteKind::Road)
+                .find(|route| parts[1].ends_with(route.label_suffix()))
+                .unwrap_or_default();
+
+            let mut journey_info = Self::new(distance, cost, route);
+            if let Some(road_type) = lines.next().and_then(|line| line.split(" (").next()) {
+                journey_info.road_type = road_type.trim().to_string();
+            }
+            Some(journey_info)
         } else {
             None
         }
     }
+
+    // Label for DOT files, the distance and cost then the road type and the journey by each travel mode
+    fn dot_label(&self) -> String {
+        if self.road_type.is_empty() {
+            return self.label();
+        }
+
+        let travel = self
+            .travel
+            .iter()
+            .map(|travel| format!("{} {:.1} h {} gold", travel.mode, travel.hours, travel.cost))
+            .join("; ");
+
+        format!(
+            "{}\\n{} (danger {:.2}, toll {} gold)\\n{}",
+            self.label(),
+            self.road_type,
+            self.danger,
+            self.toll,
+            travel
+        )
+    }
+
+    fn label(&self) -> String {
+        match self.route {
+            RouteKind::Road => format!("{} m / {} gold", self.distance, self.cost),
+            route => format!(
+                "{} m / {} gold {}",
+                self.distance,
+                self.cost,
+                route.label_suffix()
+            ),
+        }
+    }
 }
 
-// Struct for representing a building
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Building {
+// Enum for the kinds of route between towns
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, EnumIter)]
+enum RouteKind {
+    #[default]
+    Road,
+    River,
+    Sea,
+    Trail,
+}
+
+impl RouteKind {
+    // Added to the end of the DOT label so the kind of route survives importing
+    fn label_suffix(&self) -> &'static str {
+        match self {
+            RouteKind::Road => "",
+            RouteKind::River => "by river",
+            RouteKind::Sea => "by sea",
+            RouteKind::Trail => "by trail",
+        }
+    }
+}
+
+// Struct for representing a building
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Building {
     id: u32,
     name: String,
     building_type: BuildingType,
     town_id: u32,
     coords: (u32, u32),
+    #[serde(default = "default_district")]
+    district: String,
+    #[serde(default)]
+    street: String,
+    #[serde(default)]
+    address: String,
+    #[serde(default)]
+    plot: Plot,
     rooms: Vec<Room>,
+    #[serde(default)]
+    dungeon: Option<Dungeon>,
+    #[serde(default)]
+    floors: Vec<Floor>,
 }
 
-// Enum for building types
-#[derive(Serialize, Deserialize, Debug, Clone, EnumCount, EnumIter)]
-enum BuildingType {
-    Residence,
-    Shop,
-    Tavern,
-    Temple,
+// Struct for a floor of a building. Levels count up from the ground floor at 0, with cellars below it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Floor {
+    level: i32,
+    kind: FloorKind,
+    rooms: Vec<u32>,
+}
+
+// Enum for floor kinds
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+enum FloorKind {
+    Cellar,
+    Ground,
+    Upper,
+    Attic,
 }
 
+// Struct for building type, building types are defined in the settings file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+struct BuildingType(String);
+
 // Struct for representing an NPC
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Npc {
@@ -144,6 +1449,8 @@ struct Npc {
     name: String,
     sex: NpcSex,
     race: NpcRace,
+    #[serde(default)]
+    age: u32,
     town_id: u32,
     building_id: u32,
     room_id: Option<u32>,
@@ -157,12 +1464,10 @@ enum NpcSex {
     Unisex,
 }
 
-// Enum for NPC race
-#[derive(Serialize, Deserialize, Debug, Clone, EnumCount, EnumIter)]
-enum NpcRace {
-    Human,
-    Elf,
-}
+// Struct for NPC race, races are defined in the settings file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+struct NpcRace(String);
 
 // Struct for representing a room
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -172,6 +1477,106 @@ struct Room {
     building_id: u32,
     npcs: Vec<Npc>,
     containers: Vec<Container>,
+    #[serde(default)]
+    purpose: String,
+    #[serde(default)]
+    floor: i32,
+    #[serde(default)]
+    role: RoomRole,
+    #[serde(default)]
+    exits: Vec<Exit>,
+}
+
+// Enum for the part a room plays in getting around its building
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+enum RoomRole {
+    Entrance,
+    Hallway,
+    #[default]
+    Chamber,
+}
+
+// Struct for a way out of a room into another room of the same building
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Exit {
+    room_id: u32,
+    kind: ExitKind,
+    locked: bool,
+}
+
+// Enum for exit kinds, archways are never locked
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+enum ExitKind {
+    Door,
+    Archway,
+    Stairs,
+}
+
+// Struct for a dungeon's interior, its building's rooms joined by passages. A room's depth is the number of
+// passages between it and the entrance, and the boss is the ID of the NPC in the boss room if there is one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Dungeon {
+    building_id: u32,
+    town_id: u32,
+    name: String,
+    entrance: u32,
+    boss_room: u32,
+    boss: Option<u32>,
+    rooms: Vec<DungeonRoom>,
+    passages: Vec<Passage>,
+}
+
+impl Dungeon {
+    // A dungeon needs an entrance and a boss room
+    const MIN_ROOMS: usize = 2;
+}
+
+// Struct for a room in a dungeon
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DungeonRoom {
+    room_id: u32,
+    kind: DungeonRoomKind,
+    depth: u32,
+}
+
+// Enum for dungeon room kinds, corridors lead on to two rooms and dead ends to none
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+enum DungeonRoomKind {
+    Entrance,
+    Corridor,
+    Chamber,
+    DeadEnd,
+    BossRoom,
+}
+
+impl DungeonRoomKind {
+    fn name(&self) -> &'static str {
+        match self {
+            DungeonRoomKind::Entrance => "Entrance",
+            DungeonRoomKind::Corridor => "Corridor",
+            DungeonRoomKind::Chamber => "Chamber",
+            DungeonRoomKind::DeadEnd => "Dead End",
+            DungeonRoomKind::BossRoom => "Boss Room",
+        }
+    }
+
+    fn dot_shape(&self) -> &'static str {
+        match self {
+            DungeonRoomKind::Entrance => "house",
+            DungeonRoomKind::Corridor => "ellipse",
+            DungeonRoomKind::Chamber => "box",
+            DungeonRoomKind::DeadEnd => "box, style=dashed",
+            DungeonRoomKind::BossRoom => "doubleoctagon",
+        }
+    }
+}
+
+// Struct for a passage between two rooms, from the shallower room to the deeper one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Passage {
+    from: u32,
+    to: u32,
+    locked: bool,
 }
 
 // Struct for representing a container
@@ -185,7 +1590,7 @@ struct Container {
 }
 
 // Enum for container types
-#[derive(Serialize, Deserialize, Debug, Clone, EnumCount, EnumIter)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, EnumCount, EnumIter)]
 enum ContainerType {
     Barrel,
     Crate,
@@ -209,10 +1614,19 @@ impl IdTracker {
     }
 
     fn get_new_id(&mut self, settings: &AppConfig) -> u32 {
-        let mut id = self.rng.gen_range(settings.min_id..settings.max_id);
+        // Towns, stops, points of interest, buildings, rooms, NPCs and containers all share the ID range
+        let range = (settings.max_id as u64).saturating_sub(settings.min_id as u64) + 1;
+        assert!(
+            (self.ids.len() as u64) < range,
+            "Ran out of IDs, every ID from min_id ({}) to max_id ({}) is used, widen the range in settings.toml",
+            settings.min_id,
+            settings.max_id
+        );
+
+        let mut id = self.rng.gen_range(settings.min_id..=settings.max_id);
 
         while self.ids.contains(&id) {
-            id = self.rng.gen_range(settings.min_id..settings.max_id);
+            id = self.rng.gen_range(settings.min_id..=settings.max_id);
         }
         self.ids.insert(id);
 
@@ -220,590 +1634,4857 @@ impl IdTracker {
     }
 }
 
+// Struct for representing an entry in a list, with a relative weight and optional tags
+#[derive(Debug, Clone)]
+struct ListEntry {
+    value: String,
+    weight: u32,
+    tags: Vec<String>,
+}
+
+impl ListEntry {
+    // Parse a line in the format "entry | weight | tag, tag", weight and tags are optional
+    fn from_line(line: &str) -> Option<Self> {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let mut parts = line.split('|').map(|s| s.trim());
+
+        let value = parts.next()?.to_string();
+        if value.is_empty() {
+            return None;
+        }
+
+        let weight = match parts.next() {
+            Some(weight) if !weight.is_empty() => match weight.parse() {
+                Ok(weight) => weight,
+                Err(_) => {
+                    eprintln!("Skipping list entry with invalid weight: {}", line);
+                    return None;
+                }
+            },
+            _ => 1,
+        };
+
+        let tags = match parts.next() {
+            Some(tags) => tags
+                .split(',')
+                .map(|tag| tag.trim().to_lowercase())
+                .filter(|tag| !tag.is_empty())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Some(Self {
+            value,
+            weight,
+            tags,
+        })
+    }
+
+    // An entry applies when it has no tags or all of its tags are in the wanted set
+    fn matches(&self, wanted: &[String]) -> bool {
+        tags_match(&self.tags, wanted)
+    }
+}
+
+// Function to check if every one of the tags is in the wanted set
+fn tags_match(tags: &[String], wanted: &[String]) -> bool {
+    tags.iter().all(|tag| wanted.contains(tag))
+}
+
 // Function for loading in lists of names from .TXT files
-fn load_list(settings: &AppConfig, filename: &str) -> Vec<String> {
+fn load_list(settings: &AppConfig, filename: &str) -> Vec<ListEntry> {
     let filepath = format!("{}/{}", settings.input_dir, filename);
 
     match fs::File::open(filepath) {
         Ok(file) => {
             let reader = io::BufReader::new(file);
 
-            let lines: Vec<String> = reader.lines().map_while(Result::ok).collect();
-            lines
+            let entries: Vec<ListEntry> = reader
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| ListEntry::from_line(&line))
+                .collect();
+            entries
         }
         Err(e) => {
             eprint!("{}", e);
 
-            let lines = vec!["NO DATA".into()];
-            lines
+            let entries = vec![ListEntry {
+                value: "NO DATA".into(),
+                weight: 1,
+                tags: Vec::new(),
+            }];
+            entries
         }
     }
 }
 
-// Function to derive a consistent seed from a word or phrase
-fn seed_from_word(word: &str) -> u64 {
-    print!("Generating seed from word: \"{}\"... ", word);
+// Function to pick a weighted entry from a list, skipping entries whose tags don't apply
+fn choose_entry(rng: &mut StdRng, entries: &[ListEntry], tags: &[String]) -> Option<String> {
+    let mut candidates: Vec<&ListEntry> = entries.iter().filter(|e| e.matches(tags)).collect();
 
-    let mut hasher = DefaultHasher::new();
-    word.hash(&mut hasher);
+    // Fall back to untagged entries only, so a tagged entry is never used for the wrong tags
+    if candidates.is_empty() {
+        candidates = entries.iter().filter(|e| e.tags.is_empty()).collect();
+    }
 
-    println!("done!");
+    // If every candidate has a weight of 0, pick between them evenly instead of giving up
+    match candidates.choose_weighted(rng, |entry| entry.weight) {
+        Ok(entry) => Some(entry.value.clone()),
+        Err(_) => candidates.choose(rng).map(|entry| entry.value.clone()),
+    }
+}
 
-    hasher.finish()
+// Struct for a list of names, optionally backed by Markov chains for generating new names, one for each
+// set of tags in the list
+struct NameSource {
+    entries: Vec<ListEntry>,
+    markov: Vec<(Vec<String>, MarkovModel)>,
+    phonotactics: Option<Phonotactics>,
 }
 
-// Function to generate the world
-fn generate_world(settings: &AppConfig, seed: u64) -> (Graph<Town, JourneyInfo>, Vec<Town>, World) {
-    let mut rng = StdRng::seed_from_u64(seed);
-    let mut id_tracker = IdTracker::new(seed);
+impl NameSource {
+    // Generate a new name from phonotactic rules or a Markov chain if available, otherwise pick one from the list.
+    // The chain is picked from those whose tags apply, weighted by the list weights they were trained on
+    fn choose(&self, rng: &mut StdRng, tags: &[String]) -> Option<String> {
+        if let Some(rules) = &self.phonotactics {
+            return Some(rules.generate(rng));
+        }
 
-    let (graph, towns) = generate_towns(settings, &mut rng, &mut id_tracker);
+        let models: Vec<&MarkovModel> = self
+            .markov
+            .iter()
+            .filter(|(model_tags, _)| tags_match(model_tags, tags))
+            .map(|(_, model)| model)
+            .collect();
 
-    print!("Generating world... ");
+        if let Ok(model) = models.choose_weighted(rng, |model| model.weight) {
+            if let Some(name) = model.generate(rng) {
+                return Some(name);
+            }
+        }
 
-    let mut world = World {
-        towns: HashMap::new(),
-        buildings: HashMap::new(),
-        rooms: HashMap::new(),
-        npcs: HashMap::new(),
-        containers: HashMap::new(),
-    };
+        choose_entry(rng, &self.entries, tags)
+    }
+}
 
-    world.towns = towns.iter().map(|town| (town.id, town.clone())).collect();
+// Function for loading in a list of names and training a Markov chain on each set of tags in it if enabled
+fn load_name_source(settings: &AppConfig, filename: &str) -> NameSource {
+    let entries = load_list(settings, filename);
+
+    let mut markov = Vec::new();
+    if settings.markov_names {
+        let training_names: HashSet<String> = entries
+            .iter()
+            .map(|entry| entry.value.to_lowercase())
+            .collect();
+
+        // Tag sets are kept in the order they first appear so generation doesn't depend on hash map ordering
+        let mut tag_sets: Vec<Vec<String>> = Vec::new();
+        for entry in &entries {
+            let mut tags = entry.tags.clone();
+            tags.sort_unstable();
+            if !tag_sets.contains(&tags) {
+                tag_sets.push(tags);
+            }
+        }
 
-    world.buildings = towns
-        .iter()
-        .flat_map(|town| {
-            town.buildings
+        for tags in tag_sets {
+            let group: Vec<&ListEntry> = entries
                 .iter()
-                .map(|building| (building.id, building.clone()))
-        })
-        .collect();
+                .filter(|entry| entry.tags.len() == tags.len() && tags_match(&entry.tags, &tags))
+                .collect();
+            let model = MarkovModel::train(settings, &group, training_names.clone());
+            markov.push((tags, model));
+        }
+    }
 
-    world.rooms = towns
-        .iter()
-        .flat_map(|town| {
-            town.buildings
-                .iter()
-                .flat_map(|building| building.rooms.iter().map(|room| (room.id, room.clone())))
-        })
-        .collect();
+    NameSource {
+        entries,
+        markov,
+        phonotactics: None,
+    }
+}
 
-    world.npcs = towns
-        .iter()
-        .flat_map(|town| {
-            town.buildings.iter().flat_map(|building| {
-                building
-                    .rooms
-                    .iter()
-                    .flat_map(|room| room.npcs.iter().map(|npc| (npc.id, npc.clone())))
-            })
-        })
-        .collect();
+// Struct for the name lists of a naming culture
+struct CultureNames {
+    names_male: NameSource,
+    names_female: NameSource,
+    names_unisex: NameSource,
+    surnames: NameSource,
+}
 
-    world.containers = towns
-        .iter()
-        .flat_map(|town| {
-            town.buildings.iter().flat_map(|building| {
-                building.rooms.iter().flat_map(|room| {
-                    room.containers
-                        .iter()
-                        .map(|container| (container.id, container.clone()))
-                })
-            })
-        })
-        .collect();
+impl CultureNames {
+    // Get the first names for a given sex
+    fn first_names(&self, sex: &NpcSex) -> &NameSource {
+        match sex {
+            NpcSex::Male => &self.names_male,
+            NpcSex::Female => &self.names_female,
+            NpcSex::Unisex => &self.names_unisex,
+        }
+    }
+}
 
-    println!("done!");
+// Struct for the lists used to name towns of a naming culture
+struct TownNames {
+    prefixes: Vec<ListEntry>,
+    roots: NameSource,
+    suffixes: Vec<ListEntry>,
+}
 
-    (graph, towns, world)
+// Struct for the name lists used while generating a world, loaded once so Markov chains are only trained once
+struct NameLists {
+    race_names: HashMap<String, CultureNames>,
+    town_names: HashMap<Option<String>, TownNames>,
+    shops: Vec<ListEntry>,
+    taverns: Vec<ListEntry>,
+    temples: Vec<ListEntry>,
 }
 
-// Function to generate multiple towns and create a graph
-fn generate_towns(
-    settings: &AppConfig,
-    rng: &mut StdRng,
-    id_tracker: &mut IdTracker,
-) -> (Graph<Town, JourneyInfo>, Vec<Town>) {
-    print!("Generating towns... ");
+impl NameLists {
+    // Get the town name lists of a culture, or the shared lists if there is no culture
+    fn town_names(&self, culture: Option<&CultureConfig>) -> &TownNames {
+        &self.town_names[&culture.map(|culture| culture.name.clone())]
+    }
+}
 
-    let mut towns = Vec::new();
+// Struct for phonotactic rules, builds names from syllables made of an onset, a vowel and a coda
+#[derive(Debug, Clone)]
+struct Phonotactics {
+    onsets: Vec<String>,
+    vowels: Vec<String>,
+    codas: Vec<String>,
+    min_syllables: u32,
+    max_syllables: u32,
+}
 
-    let prefixes = load_list(settings, "town-prefixes.txt");
-    let roots = load_list(settings, "town-roots.txt");
-    let suffixes = load_list(settings, "town-suffixes.txt");
+impl Phonotactics {
+    // Get the phonotactic rules of a culture, returns None if it doesn't define any vowels
+    fn from_culture(culture: &CultureConfig) -> Option<Self> {
+        if culture.vowels.is_empty() {
+            return None;
+        }
 
-    for _ in 0..settings.num_of_towns {
-        let town_id = id_tracker.get_new_id(settings);
+        Some(Self {
+            onsets: culture.onsets.clone(),
+            vowels: culture.vowels.clone(),
+            codas: culture.codas.clone(),
+            min_syllables: culture.min_syllables.max(1),
+            max_syllables: culture.max_syllables.max(culture.min_syllables.max(1)),
+        })
+    }
 
-        let number_of_buildings = rng.gen_range(settings.min_buildings..settings.max_buildings);
-        let buildings =
-            generate_buildings(settings, rng, id_tracker, &town_id, number_of_buildings);
+    // Generate a name, only the last syllable gets a coda
+    fn generate(&self, rng: &mut StdRng) -> String {
+        let syllables = rng.gen_range(self.min_syllables..=self.max_syllables);
+        let mut name = String::new();
 
-        towns.push(Town {
-            id: town_id,
-            name: generate_town_name(rng, &prefixes, &roots, &suffixes),
-            coords: (0, 0),
-            number_of_buildings,
-            buildings,
-        });
+        for i in 0..syllables {
+            if let Some(onset) = self.onsets.choose(rng) {
+                name.push_str(onset);
+            }
+            if let Some(vowel) = self.vowels.choose(rng) {
+                name.push_str(vowel);
+            }
+            if i == syllables - 1 {
+                if let Some(coda) = self.codas.choose(rng) {
+                    name.push_str(coda);
+                }
+            }
+        }
+
+        capitalize(&name.to_lowercase())
     }
+}
 
-    println!("done!");
+// Function to get the naming culture of a race, if there is one
+fn culture_for_race<'a>(settings: &'a AppConfig, race: &RaceConfig) -> Option<&'a CultureConfig> {
+    if let Some(name) = &race.culture {
+        return settings
+            .cultures
+            .iter()
+            .find(|culture| culture.name.eq_ignore_ascii_case(name));
+    }
+
+    settings.cultures.iter().find(|culture| {
+        culture
+            .races
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&race.name))
+    })
+}
 
-    let (graph, nodes) = generate_graph(settings, rng, towns);
+// Function to get the path of a list for a culture, falls back to the shared list if the culture doesn't have its own
+fn culture_list_path(
+    settings: &AppConfig,
+    culture: Option<&CultureConfig>,
+    filename: &str,
+) -> String {
+    if let Some(folder) = culture.and_then(|culture| culture.lists.as_ref()) {
+        let path = format!("{}/{}", folder, filename);
 
-    let list_of_towns = nodes.iter().map(|&node| graph[node].clone()).collect();
+        if std::path::Path::new(&format!("{}/{}", settings.input_dir, path)).exists() {
+            return path;
+        }
+    }
 
-    (graph, list_of_towns)
+    filename.to_string()
 }
 
-// Generate a town name using a prefix-root-suffix combination
-fn generate_town_name(
-    rng: &mut StdRng,
-    prefixes: &[String],
-    roots: &[String],
-    suffixes: &[String],
-) -> String {
-    let prefix: String;
-    if let Some(name) = prefixes.get(rng.gen_range(0..prefixes.len())) {
-        prefix = name.to_string();
-    } else {
-        prefix = "NO DATA".into();
+// Function for loading in the name lists of a naming culture, or the shared lists if there is no culture
+fn load_culture_names(settings: &AppConfig, culture: Option<&CultureConfig>) -> CultureNames {
+    let load = |filename: &str| {
+        let mut names = load_name_source(settings, &culture_list_path(settings, culture, filename));
+        names.phonotactics = culture.and_then(Phonotactics::from_culture);
+        names
+    };
+
+    CultureNames {
+        names_male: load("names-male.txt"),
+        names_female: load("names-female.txt"),
+        names_unisex: load("names-unisex.txt"),
+        surnames: load("surnames.txt"),
     }
+}
 
-    let root: String;
-    if let Some(name) = roots.get(rng.gen_range(0..roots.len())) {
-        root = name.to_string();
-    } else {
-        root = "NO DATA".into();
+// Function for loading in the lists used to name towns of a naming culture, or the shared lists if there is
+// no culture
+fn load_town_names(settings: &AppConfig, culture: Option<&CultureConfig>) -> TownNames {
+    TownNames {
+        prefixes: load_list(
+            settings,
+            &culture_list_path(settings, culture, "town-prefixes.txt"),
+        ),
+        roots: load_name_source(
+            settings,
+            &culture_list_path(settings, culture, "town-roots.txt"),
+        ),
+        suffixes: load_list(
+            settings,
+            &culture_list_path(settings, culture, "town-suffixes.txt"),
+        ),
+    }
+}
+
+// Function for loading in the name lists used while generating a world
+fn load_name_lists(settings: &AppConfig) -> NameLists {
+    // Towns only get a culture if town cultures are enabled
+    let cultures = settings
+        .cultures
+        .iter()
+        .filter(|_| settings.town_cultures)
+        .map(Some);
+
+    NameLists {
+        race_names: load_race_names(settings),
+        town_names: std::iter::once(None)
+            .chain(cultures)
+            .map(|culture| {
+                (
+                    culture.map(|culture| culture.name.clone()),
+                    load_town_names(settings, culture),
+                )
+            })
+            .collect(),
+        shops: load_list(settings, "shops.txt"),
+        taverns: load_list(settings, "taverns.txt"),
+        temples: load_list(settings, "temples.txt"),
     }
+}
 
-    let suffix: String;
-    if let Some(name) = suffixes.get(rng.gen_range(0..suffixes.len())) {
-        suffix = name.to_string();
+// Function to pick a culture for a town, returns None if town cultures are disabled
+fn choose_town_culture<'a>(settings: &'a AppConfig, rng: &mut StdRng) -> Option<&'a CultureConfig> {
+    if settings.town_cultures {
+        settings.cultures.choose(rng)
     } else {
-        suffix = "NO DATA".into();
+        None
     }
+}
 
-    format!("{} {}{}", prefix, root, suffix)
+// Struct for a character-level Markov chain used to generate names
+struct MarkovModel {
+    order: usize,
+    min_length: usize,
+    max_length: usize,
+    allow_training_names: bool,
+    transitions: HashMap<String, Vec<(char, u32)>>,
+    training_names: HashSet<String>,
+    weight: u32,
 }
 
-// Function to generate buildings
-fn generate_buildings(
-    settings: &AppConfig,
-    rng: &mut StdRng,
-    id_tracker: &mut IdTracker,
-    town_id: &u32,
-    number_of_buildings: u32,
-) -> Vec<Building> {
-    let mut buildings = Vec::new();
+impl MarkovModel {
+    const START: char = '^';
+    const END: char = '$';
+    const MAX_ATTEMPTS: usize = 100;
+
+    // Train a model on entries of a list, weighting each entry by its list weight. Generated names identical to
+    // one of the training names are rejected unless allowed
+    fn train(
+        settings: &AppConfig,
+        entries: &[&ListEntry],
+        training_names: HashSet<String>,
+    ) -> Self {
+        let order = settings.markov_order.max(1);
+
+        let mut counts: HashMap<String, HashMap<char, u32>> = HashMap::new();
+
+        for entry in entries {
+            let name = entry.value.to_lowercase();
+
+            let chars: Vec<char> = std::iter::repeat_n(Self::START, order)
+                .chain(name.chars())
+                .chain(std::iter::once(Self::END))
+                .collect();
+
+            for window in chars.windows(order + 1) {
+                let key: String = window[..order].iter().collect();
+                *counts
+                    .entry(key)
+                    .or_default()
+                    .entry(window[order])
+                    .or_insert(0) += entry.weight;
+            }
+        }
 
-    let surnames = load_list(settings, "surnames.txt");
-    let shops = load_list(settings, "shops.txt");
-    let taverns = load_list(settings, "taverns.txt");
-    let temples = load_list(settings, "temples.txt");
+        // Sort the transitions so generation doesn't depend on hash map ordering
+        let transitions = counts
+            .into_iter()
+            .map(|(key, next)| {
+                let mut next: Vec<(char, u32)> = next.into_iter().collect();
+                next.sort_unstable();
+                (key, next)
+            })
+            .collect();
 
-    let grid_size = (number_of_buildings as f32).sqrt().ceil() as u32;
-    let mut position = (0, 0);
+        Self {
+            order,
+            min_length: settings.markov_min_length,
+            max_length: settings.markov_max_length.max(settings.markov_min_length),
+            allow_training_names: settings.markov_allow_training_names,
+            transitions,
+            training_names,
+            weight: entries.iter().map(|entry| entry.weight).sum(),
+        }
+    }
 
-    for _ in 0..number_of_buildings {
-        let building_id = id_tracker.get_new_id(settings);
+    // Generate a name, returns None if no suitable name was found
+    fn generate(&self, rng: &mut StdRng) -> Option<String> {
+        for _ in 0..Self::MAX_ATTEMPTS {
+            let mut key: String = std::iter::repeat_n(Self::START, self.order).collect();
+            let mut name = String::new();
 
-        let building_type = match rng.gen_range(0..BuildingType::COUNT) {
-            0 => BuildingType::Residence,
-            1 => BuildingType::Shop,
-            2 => BuildingType::Tavern,
-            3 => BuildingType::Temple,
-            _ => BuildingType::Residence,
-        };
+            while let Some(next) = self.transitions.get(&key) {
+                let next = next.choose_weighted(rng, |&(_, weight)| weight).ok()?.0;
 
-        let mut building = Building {
-            id: building_id,
-            name: generate_building_name(
-                rng,
-                &building_type,
-                &surnames,
-                &shops,
-                &taverns,
-                &temples,
-            ),
-            building_type,
-            town_id: *town_id,
-            coords: position,
-            rooms: Vec::new(),
-        };
+                if next == Self::END {
+                    break;
+                }
 
-        let mut npcs = generate_npcs(
-            settings,
-            rng,
-            id_tracker,
-            town_id,
-            &building_id,
-            &building.name,
-            &building.building_type,
-        );
+                name.push(next);
+                key = key.chars().skip(1).chain(std::iter::once(next)).collect();
 
-        building.rooms =
-            generate_rooms(settings, rng, id_tracker, town_id, &building_id, &mut npcs);
+                // Names that reach the maximum length are cut off there
+                if name.chars().count() >= self.max_length {
+                    break;
+                }
+            }
 
-        buildings.push(building);
+            if name.chars().count() < self.min_length {
+                continue;
+            }
+            if !self.allow_training_names && self.training_names.contains(&name) {
+                continue;
+            }
 
-        position.0 += 1;
-        if position.0 >= grid_size {
-            position.0 = 0;
-            position.1 += 1;
+            return Some(capitalize(&name));
         }
-    }
 
-    buildings
+        None
+    }
 }
 
-// Generate a building name
-fn generate_building_name(
-    rng: &mut StdRng,
-    building_type: &BuildingType,
-    surnames: &[String],
-    shops: &[String],
-    taverns: &[String],
-    temples: &[String],
-) -> String {
-    match building_type {
-        BuildingType::Residence => {
-            if let Some(name) = surnames.get(rng.gen_range(0..surnames.len())) {
-                format!("{} Residence", name)
-            } else {
-                "NO DATA".into()
-            }
+// Function to capitalize the first letter of each word in a name
+fn capitalize(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut start_of_word = true;
+
+    for c in name.chars() {
+        if start_of_word {
+            result.extend(c.to_uppercase());
+        } else {
+            result.push(c);
         }
-        BuildingType::Shop => {
-            if let Some(name) = surnames.get(rng.gen_range(0..surnames.len())) {
-                if let Some(shop) = shops.get(rng.gen_range(0..shops.len())) {
-                    format!("{}'s {}", name, shop)
-                } else {
-                    "NO DATA".into()
+        start_of_word = c == ' ' || c == '-';
+    }
+
+    result
+}
+
+// Struct for a Tracery-style grammar, maps rule names to a list of alternative templates
+struct Grammar {
+    rules: HashMap<String, Vec<String>>,
+}
+
+// Enum for values the generator binds to grammar symbols
+enum GrammarSymbol<'a> {
+    Text(String),
+    List(&'a [ListEntry]),
+    Names(&'a NameSource),
+}
+
+impl Grammar {
+    const MAX_DEPTH: usize = 20;
+
+    // Default rules, these reproduce the built-in name formats
+    const DEFAULT_RULES: [(&'static str, &'static [&'static str]); 23] = [
+        ("town", &["#prefix# #root##suffix#"]),
+        ("crossroads", &["#root# Crossroads", "#root# Cross"]),
+        ("waystation", &["#root# Waystation", "#root# Post House"]),
+        ("bridge", &["#root# Bridge"]),
+        ("ford", &["#root# Ford"]),
+        ("ruins", &["Ruins of #root#", "#root# Ruins"]),
+        ("cave", &["#root# Cave", "#root# Hollow"]),
+        ("shrine", &["Shrine of #root#", "#root# Shrine"]),
+        ("bandit_camp", &["#root# Camp", "#root# Hideout"]),
+        (
+            "dungeon",
+            &["#root# Keep", "#root# Depths", "#root# Barrow"],
+        ),
+        ("residence", &["#surname# Residence"]),
+        ("shop", &["#surname.possessive# #shop_type#"]),
+        ("tavern", &["#tavern_name#"]),
+        ("temple", &["Temple of the #temple_dedication#"]),
+        ("npc_residence", &["#firstname# #surname#"]),
+        ("npc_shop", &["#firstname# #surname#"]),
+        ("npc_tavern", &["#firstname# #surname#"]),
+        ("npc_temple", &["#firstname# of the #building#"]),
+        ("npc", &["#firstname# #surname#"]),
+        (
+            "square",
+            &["Market Square", "Town Square", "Old Square", "Crown Square"],
+        ),
+        ("street", &["#street_name# #street_kind#"]),
+        (
+            "street_name",
+            &[
+                "Mill", "Church", "High", "King's", "Queen's", "Bridge", "Water", "Castle",
+                "Silver", "Baker", "Tanner", "Cooper", "Fisher", "Old", "New", "Green",
+            ],
+        ),
+        ("street_kind", &["Street", "Lane", "Road", "Row", "Way"]),
+    ];
+
+    // Check if every # in a template is closed by another one
+    fn is_balanced(template: &str) -> bool {
+        template.matches('#').count() % 2 == 0
+    }
+
+    // Check if the grammar has a rule
+    fn has_rule(&self, rule: &str) -> bool {
+        self.rules.contains_key(rule)
+    }
+
+    // Generate text by expanding a template
+    fn generate_from_template(
+        &self,
+        rng: &mut StdRng,
+        template: &str,
+        symbols: &HashMap<&str, GrammarSymbol>,
+        tags: &[String],
+    ) -> String {
+        self.flatten(rng, template, symbols, tags, 0)
+    }
+
+    // Generate text by expanding a rule
+    fn generate(
+        &self,
+        rng: &mut StdRng,
+        rule: &str,
+        symbols: &HashMap<&str, GrammarSymbol>,
+        tags: &[String],
+    ) -> String {
+        self.expand_symbol(rng, rule, symbols, tags, 0)
+    }
+
+    // Expand every #symbol# in a template
+    fn flatten(
+        &self,
+        rng: &mut StdRng,
+        template: &str,
+        symbols: &HashMap<&str, GrammarSymbol>,
+        tags: &[String],
+        depth: usize,
+    ) -> String {
+        let parts: Vec<&str> = template.split('#').collect();
+
+        parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                // A # that isn't closed is kept as it is
+                if i % 2 == 1 && i == parts.len() - 1 {
+                    format!("#{}", part)
+                } else if i % 2 == 1 {
+                    self.expand_symbol(rng, part, symbols, tags, depth + 1)
+                } else {
+                    part.to_string()
                 }
-            } else {
-                "NO DATA".into()
+            })
+            .collect()
+    }
+
+    // Expand a single symbol, e.g. "surname.possessive", bound symbols take priority over rules
+    fn expand_symbol(
+        &self,
+        rng: &mut StdRng,
+        symbol: &str,
+        symbols: &HashMap<&str, GrammarSymbol>,
+        tags: &[String],
+        depth: usize,
+    ) -> String {
+        let mut parts = symbol.split('.');
+        let name = parts.next().unwrap_or_default().trim();
+
+        let text = if depth > Self::MAX_DEPTH {
+            None
+        } else {
+            match symbols.get(name) {
+                Some(GrammarSymbol::Text(text)) => Some(text.clone()),
+                Some(GrammarSymbol::List(entries)) => choose_entry(rng, entries, tags),
+                Some(GrammarSymbol::Names(names)) => names.choose(rng, tags),
+                None => self
+                    .rules
+                    .get(name)
+                    .and_then(|templates| templates.choose(rng).cloned())
+                    .map(|template| self.flatten(rng, &template, symbols, tags, depth)),
+            }
+        };
+
+        let text = text.unwrap_or_else(|| "NO DATA".into());
+
+        parts.fold(text, |text, modifier| {
+            apply_modifier(&text, modifier.trim())
+        })
+    }
+}
+
+// Function to apply a grammar modifier to expanded text
+fn apply_modifier(text: &str, modifier: &str) -> String {
+    match modifier {
+        "capitalize" => {
+            let mut chars = text.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
             }
         }
-        BuildingType::Tavern => {
-            if let Some(name) = taverns.get(rng.gen_range(0..taverns.len())) {
-                name.to_string()
+        "title" => capitalize(text),
+        "upper" => text.to_uppercase(),
+        "lower" => text.to_lowercase(),
+        "s" | "plural" => {
+            let lower = text.to_lowercase();
+            if lower.ends_with('s')
+                || lower.ends_with('x')
+                || lower.ends_with("ch")
+                || lower.ends_with("sh")
+            {
+                format!("{}es", text)
+            } else if lower.ends_with('y')
+                && !lower.ends_with("ay")
+                && !lower.ends_with("ey")
+                && !lower.ends_with("oy")
+            {
+                format!("{}ies", &text[..text.len() - 1])
             } else {
-                "NO DATA".into()
+                format!("{}s", text)
             }
         }
-        BuildingType::Temple => {
-            if let Some(temple) = temples.get(rng.gen_range(0..temples.len())) {
-                format!("Temple of the {}", temple)
+        "possessive" => {
+            if text.ends_with('s') {
+                format!("{}'", text)
             } else {
-                "NO DATA".into()
+                format!("{}'s", text)
+            }
+        }
+        "a" => match text.chars().next() {
+            Some(c) if "aeiouAEIOU".contains(c) => format!("an {}", text),
+            _ => format!("a {}", text),
+        },
+        _ => text.to_string(),
+    }
+}
+
+// Function for loading in the grammar file, rules in the file override the default rules
+fn load_grammar(settings: &AppConfig) -> Grammar {
+    let mut rules: HashMap<String, Vec<String>> = Grammar::DEFAULT_RULES
+        .iter()
+        .map(|(name, templates)| {
+            let templates = templates.iter().map(|template| template.to_string());
+            (name.to_string(), templates.collect())
+        })
+        .collect();
+
+    for type_config in &settings.building_types {
+        if let Some(template) = &type_config.name_template {
+            if !Grammar::is_balanced(template) {
+                eprintln!(
+                    "Name template of building type \"{}\" has an unbalanced #: {}",
+                    type_config.name, template
+                );
+            }
+        }
+    }
+
+    let filepath = format!("{}/{}", settings.input_dir, settings.grammar_file);
+
+    if !std::path::Path::new(&filepath).exists() {
+        return Grammar { rules };
+    }
+
+    let file_contents = Config::builder()
+        .add_source(File::new(&filepath, FileFormat::Toml))
+        .build()
+        .and_then(|config| config.try_deserialize::<HashMap<String, config::Value>>());
+
+    match file_contents {
+        Ok(file_rules) => {
+            for (name, value) in file_rules {
+                let templates: Vec<String> = match value.clone().into_array() {
+                    Ok(values) => values
+                        .into_iter()
+                        .filter_map(|value| value.into_string().ok())
+                        .collect(),
+                    Err(_) => value.into_string().into_iter().collect(),
+                };
+
+                // Templates with an unbalanced # are reported and left out
+                let templates = templates
+                    .into_iter()
+                    .filter(|template| {
+                        let balanced = Grammar::is_balanced(template);
+                        if !balanced {
+                            eprintln!(
+                                "Skipping template of grammar rule \"{}\" with an unbalanced #: {}",
+                                name, template
+                            );
+                        }
+                        balanced
+                    })
+                    .collect();
+
+                rules.insert(name, templates);
+            }
+        }
+        Err(e) => eprint!("{}", e),
+    }
+
+    Grammar { rules }
+}
+
+// Function to derive a consistent seed from a word or phrase
+fn seed_from_word(word: &str) -> u64 {
+    print!("Generating seed from word: \"{}\"... ", word);
+
+    let mut hasher = DefaultHasher::new();
+    word.hash(&mut hasher);
+
+    println!("done!");
+
+    hasher.finish()
+}
+
+// Function to generate the world
+fn generate_world(settings: &AppConfig, seed: u64) -> GenerateResult {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut id_tracker = IdTracker::new(seed);
+
+    let (graph, towns, terrain, network) = generate_towns(settings, &mut rng, &mut id_tracker)?;
+
+    print!("Generating world... ");
+
+    let mut world = World {
+        towns: HashMap::new(),
+        buildings: HashMap::new(),
+        rooms: HashMap::new(),
+        npcs: HashMap::new(),
+        containers: HashMap::new(),
+        roads: Vec::new(),
+        terrain,
+        network,
+        dungeons: HashMap::new(),
+    };
+
+    world.towns = towns.iter().map(|town| (town.id, town.clone())).collect();
+    world.roads = roads_from_graph(&graph);
+
+    world.buildings = towns
+        .iter()
+        .flat_map(|town| {
+            town.buildings
+                .iter()
+                .map(|building| (building.id, building.clone()))
+        })
+        .collect();
+
+    world.rooms = towns
+        .iter()
+        .flat_map(|town| {
+            town.buildings
+                .iter()
+                .flat_map(|building| building.rooms.iter().map(|room| (room.id, room.clone())))
+        })
+        .collect();
+
+    world.npcs = towns
+        .iter()
+        .flat_map(|town| {
+            town.buildings.iter().flat_map(|building| {
+                building
+                    .rooms
+                    .iter()
+                    .flat_map(|room| room.npcs.iter().map(|npc| (npc.id, npc.clone())))
+            })
+        })
+        .collect();
+
+    world.containers = towns
+        .iter()
+        .flat_map(|town| {
+            town.buildings.iter().flat_map(|building| {
+                building.rooms.iter().flat_map(|room| {
+                    room.containers
+                        .iter()
+                        .map(|container| (container.id, container.clone()))
+                })
+            })
+        })
+        .collect();
+
+    world.dungeons = towns
+        .iter()
+        .flat_map(|town| {
+            town.buildings.iter().filter_map(|building| {
+                building
+                    .dungeon
+                    .as_ref()
+                    .map(|dungeon| (building.id, dungeon.clone()))
+            })
+        })
+        .collect();
+
+    println!("done!");
+
+    Ok((graph, towns, world))
+}
+
+// Type alias for the generate_world function's complex return type
+type GenerateResult = Result<(Graph<Town, JourneyInfo>, Vec<Town>, World), std::io::Error>;
+
+// Function to generate multiple towns and create a graph. The roads are generated first so the towns can be
+// placed on the terrain, and each town's biome is known when generating its name and buildings
+fn generate_towns(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+) -> TownsResult {
+    let (mut sites, nodes) = generate_graph(settings, rng, (0..settings.num_of_towns).collect());
+    let coords = generate_town_coords(rng, &sites);
+    let (terrain, placements) = place_towns_on_terrain(settings, rng, &coords);
+    let coords: Vec<(u32, u32)> = placements.iter().map(|(coords, _)| *coords).collect();
+    let water_map = WaterMap::new(settings, &terrain, &coords);
+    let harbours = find_harbours(settings, &terrain, &water_map, &coords);
+    add_water_routes(
+        settings, &mut sites, &coords, &terrain, &water_map, &harbours,
+    );
+
+    let grammar = load_grammar(settings);
+    let lists = load_name_lists(settings);
+
+    // Towns are named first so the stops along the roads and points of interest can be added, their buildings
+    // are generated once the network has been analysed and the capital is known
+    let cultures: Vec<Option<&CultureConfig>> = placements
+        .iter()
+        .map(|_| choose_town_culture(settings, rng))
+        .collect();
+    let mut used_names = HashSet::new();
+    let towns: Vec<Town> = placements
+        .iter()
+        .zip(&harbours)
+        .zip(&cultures)
+        .map(|((&(coords, biome), harbour), &culture)| Town {
+            id: id_tracker.get_new_id(settings),
+            name: unique_name(
+                generate_town_name(rng, &grammar, culture, biome, lists.town_names(culture)),
+                &mut used_names,
+            ),
+            culture: culture.map(|culture| culture.name.clone()),
+            size: TownSize::default(),
+            population: 0,
+            coords,
+            biome: Biome(biome.name.clone()),
+            port: harbour.is_port(),
+            kind: TownKind::Town,
+            capital: false,
+            metrics: TownMetrics::default(),
+            number_of_buildings: 0,
+            buildings: Vec::new(),
+            layout: TownLayout::default(),
+        })
+        .collect();
+
+    let mut graph = sites.map(
+        |node, _| towns[node.index()].clone(),
+        |_, edge| edge.clone(),
+    );
+    classify_routes(settings, &mut graph, &terrain);
+
+    // Roads split by stops keep their road type, the new roads are classified again for their travel times
+    add_road_stops(
+        settings, rng, id_tracker, &lists, &grammar, &mut graph, &terrain,
+    );
+    add_points_of_interest(
+        settings, rng, id_tracker, &lists, &grammar, &mut graph, &terrain,
+    );
+    classify_routes(settings, &mut graph, &terrain);
+
+    // The whole network is analysed, stops and points of interest included, as it is when importing
+    let (metrics, mut network) = analyse_network(&graph);
+    let kinds: Vec<TownKind> = graph.node_weights().map(|town| town.kind).collect();
+    let capital = choose_capital(settings, &metrics, &kinds);
+    for (node, metrics) in graph.node_indices().zip(metrics) {
+        graph[node].metrics = metrics;
+    }
+
+    print!("Generating towns... ");
+
+    let mut towns: Vec<Town> = Vec::new();
+
+    for (index, ((&(_, biome), harbour), &culture)) in
+        placements.iter().zip(&harbours).zip(&cultures).enumerate()
+    {
+        let mut town = graph[NodeIndex::new(index)].clone();
+
+        let is_capital = capital == Some(index);
+        let size = capital_size(settings, is_capital)
+            .unwrap_or_else(|| generate_town_size(settings, rng, &towns));
+
+        let number_of_buildings = capital_buildings(
+            settings,
+            rng.gen_range(size.min_buildings..=size.max_buildings.max(size.min_buildings)),
+            is_capital,
+        );
+        let mut buildings = generate_buildings(
+            settings,
+            rng,
+            id_tracker,
+            &lists,
+            &grammar,
+            &town.id,
+            culture,
+            size,
+            biome,
+            number_of_buildings,
+        )?;
+        if harbour.is_port() {
+            assign_port_districts(settings, &mut buildings);
+        }
+
+        town.layout = generate_layout(rng, &grammar, &mut buildings);
+        town.size = TownSize(size.name.clone());
+        town.population = calculate_population(size, &buildings);
+        town.capital = is_capital;
+        town.number_of_buildings = buildings.len() as u32;
+        town.buildings = buildings;
+        towns.push(town);
+    }
+
+    println!("done!");
+
+    network.capital = capital.map(|index| towns[index].id);
+
+    for (index, town) in towns.into_iter().enumerate() {
+        graph[NodeIndex::new(index)] = town;
+    }
+
+    let list_of_towns = nodes
+        .iter()
+        .map(|&node| graph[node].clone())
+        .chain(graph.node_weights().skip(nodes.len()).cloned())
+        .collect();
+
+    Ok((graph, list_of_towns, terrain, network))
+}
+
+// Type alias for the generate_towns function's complex return type
+type TownsResult =
+    Result<(Graph<Town, JourneyInfo>, Vec<Town>, Terrain, NetworkMetrics), std::io::Error>;
+
+// Pick the capital, the town with the highest closeness centrality, if the world has one. Stops along the
+// roads can't be the capital
+fn choose_capital(
+    settings: &AppConfig,
+    metrics: &[TownMetrics],
+    kinds: &[TownKind],
+) -> Option<usize> {
+    if !settings.capital {
+        return None;
+    }
+
+    metrics
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| kinds[*index] == TownKind::Town)
+        .max_by(|(_, a), (_, b)| {
+            a.closeness
+                .total_cmp(&b.closeness)
+                .then(a.betweenness.total_cmp(&b.betweenness))
+        })
+        .map(|(index, _)| index)
+}
+
+// Get the size of the capital if it's set, None for other towns or if there's no such size
+fn capital_size(settings: &AppConfig, is_capital: bool) -> Option<&TownSizeConfig> {
+    if !is_capital {
+        return None;
+    }
+
+    settings.town_sizes.iter().find(|capital_size| {
+        capital_size
+            .name
+            .eq_ignore_ascii_case(&settings.capital_size)
+    })
+}
+
+// Get the number of buildings of a town, the capital's is scaled up
+fn capital_buildings(settings: &AppConfig, number_of_buildings: u32, is_capital: bool) -> u32 {
+    if is_capital {
+        (number_of_buildings as f64 * settings.capital_scale).round() as u32
+    } else {
+        number_of_buildings
+    }
+}
+
+// Calculate the centrality of each town and the metrics of the whole network from the shortest distances
+// along the roads. Every route is at least 1 long so shortest paths can be counted in order of distance
+fn analyse_network<N>(graph: &Graph<N, JourneyInfo>) -> (Vec<TownMetrics>, NetworkMetrics) {
+    let n = graph.node_count();
+
+    let mut distances = vec![vec![u32::MAX; n]; n];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[i] = 0;
+    }
+    for edge in graph.edge_references() {
+        let (a, b) = (edge.source().index(), edge.target().index());
+        let distance = edge.weight().distance.max(1).min(distances[a][b]);
+        distances[a][b] = distance;
+        distances[b][a] = distance;
+    }
+    let direct = distances.clone();
+    for k in 0..n {
+        for i in 0..n {
+            for j in 0..n {
+                let through_k = distances[i][k].saturating_add(distances[k][j]);
+                if through_k < distances[i][j] {
+                    distances[i][j] = through_k;
+                }
+            }
+        }
+    }
+
+    // Number of shortest paths between every pair of towns, counted outwards from each town in order of
+    // distance
+    let mut paths = vec![vec![0.0; n]; n];
+    for s in 0..n {
+        paths[s][s] = 1.0;
+        let order = (0..n)
+            .filter(|&t| t != s && distances[s][t] != u32::MAX)
+            .sorted_by_key(|&t| distances[s][t]);
+        for t in order {
+            paths[s][t] = (0..n)
+                .filter(|&u| {
+                    u != t
+                        && direct[u][t] != u32::MAX
+                        && distances[s][u].saturating_add(direct[u][t]) == distances[s][t]
+                })
+                .map(|u| paths[s][u])
+                .sum();
+        }
+    }
+
+    let mut degrees = vec![0; n];
+    for edge in graph.edge_references() {
+        degrees[edge.source().index()] += 1;
+        degrees[edge.target().index()] += 1;
+    }
+
+    let pairs = ((n.saturating_sub(1)) * (n.saturating_sub(2)) / 2).max(1) as f64;
+    let metrics = (0..n)
+        .map(|v| {
+            let reachable: Vec<u32> = (0..n)
+                .filter(|&t| t != v && distances[v][t] != u32::MAX)
+                .map(|t| distances[v][t])
+                .collect();
+            let total: u64 = reachable.iter().map(|&d| d as u64).sum();
+
+            let betweenness = (0..n)
+                .tuple_combinations()
+                .filter(|&(s, t)| {
+                    s != v
+                        && t != v
+                        && distances[s][t] != u32::MAX
+                        && distances[s][v].saturating_add(distances[v][t]) == distances[s][t]
+                })
+                .map(|(s, t)| paths[s][v] * paths[v][t] / paths[s][t])
+                .fold(0.0, |total, share| total + share);
+
+            TownMetrics {
+                degree: degrees[v],
+                betweenness: (betweenness / pairs * 1000.0).round() / 1000.0,
+                closeness: if total > 0 {
+                    (reachable.len() as f64 / total as f64 * 10000.0).round() / 10000.0
+                } else {
+                    0.0
+                },
+                eccentricity: reachable.iter().copied().max().unwrap_or_default(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let path_lengths: Vec<u32> = distances
+        .iter()
+        .enumerate()
+        .flat_map(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .filter(move |&(j, &d)| i != j && d != u32::MAX)
+                .map(|(_, &d)| d)
+        })
+        .collect();
+    let network = NetworkMetrics {
+        diameter: metrics
+            .iter()
+            .map(|metrics| metrics.eccentricity)
+            .max()
+            .unwrap_or_default(),
+        average_path_length: if path_lengths.is_empty() {
+            0.0
+        } else {
+            let average =
+                path_lengths.iter().map(|&d| d as f64).sum::<f64>() / path_lengths.len() as f64;
+            (average * 10.0).round() / 10.0
+        },
+        capital: None,
+    };
+
+    (metrics, network)
+}
+
+// Pick a size for a town, skipping sizes that have reached their maximum count. The capital size is kept for
+// the capital when there is one
+fn generate_town_size<'a>(
+    settings: &'a AppConfig,
+    rng: &mut StdRng,
+    towns: &[Town],
+) -> &'a TownSizeConfig {
+    let available: Vec<&TownSizeConfig> = settings
+        .town_sizes
+        .iter()
+        .filter(|size| !settings.capital || !size.name.eq_ignore_ascii_case(&settings.capital_size))
+        .filter(|size| match size.max_count {
+            Some(max_count) => {
+                towns.iter().filter(|town| town.size.0 == size.name).count() < max_count as usize
+            }
+            None => true,
+        })
+        .collect();
+
+    match available.choose_weighted(rng, |size| size.weight) {
+        Ok(size) => size,
+        Err(_) => &settings.town_sizes[0],
+    }
+}
+
+// Calculate a town's population, each NPC represents a number of inhabitants based on the town's size
+fn calculate_population(size: &TownSizeConfig, buildings: &[Building]) -> u32 {
+    let number_of_npcs: usize = buildings
+        .iter()
+        .flat_map(|building| building.rooms.iter())
+        .map(|room| room.npcs.len())
+        .sum();
+
+    number_of_npcs as u32 * size.population_multiplier
+}
+
+// Function to move the buildings of a port town into their types' port districts, e.g. the docks
+fn assign_port_districts(settings: &AppConfig, buildings: &mut [Building]) {
+    for building in buildings {
+        let port_district = settings
+            .building_types
+            .iter()
+            .find(|type_config| type_config.name == building.building_type.0)
+            .and_then(|type_config| type_config.port_district.as_ref());
+
+        if let Some(district) = port_district {
+            building.district = district.clone();
+        }
+    }
+}
+
+// Generate a town's layout, buildings are grouped by district and placed on plots along streets leading
+// out of a central square, alternating sides of the street
+fn generate_layout(rng: &mut StdRng, grammar: &Grammar, buildings: &mut [Building]) -> TownLayout {
+    const FRONTAGE: i32 = 10;
+    const DEPTH: i32 = 15;
+    const STREET_HALF_WIDTH: i32 = 3;
+    // Large enough that plots on neighbouring streets don't overlap at the corners of the square
+    const SQUARE_HALF_SIZE: i32 = STREET_HALF_WIDTH + DEPTH;
+
+    // Small towns have a single road running through, larger ones a crossroads
+    let directions: &[(i32, i32)] = if buildings.len() <= 6 {
+        &[(1, 0), (-1, 0)]
+    } else {
+        &[(0, -1), (1, 0), (0, 1), (-1, 0)]
+    };
+
+    let symbols = HashMap::new();
+    let square_name = grammar.generate(rng, "square", &symbols, &[]);
+
+    let mut street_names: Vec<String> = Vec::new();
+    for _ in directions {
+        let mut name = grammar.generate(rng, "street", &symbols, &[]);
+        for _ in 0..10 {
+            if !street_names.contains(&name) {
+                break;
             }
+            name = grammar.generate(rng, "street", &symbols, &[]);
+        }
+        street_names.push(name);
+    }
+
+    // Largest districts first, so they get the plots closest to the square
+    let mut districts: Vec<(String, Vec<usize>)> = Vec::new();
+    for (i, building) in buildings.iter().enumerate() {
+        match districts
+            .iter_mut()
+            .find(|(name, _)| *name == building.district)
+        {
+            Some((_, indices)) => indices.push(i),
+            None => districts.push((building.district.clone(), vec![i])),
         }
     }
+    districts.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+
+    let plots_per_street = buildings.len().div_ceil(directions.len()).max(1);
+
+    // Place plots around the origin at the centre of the square
+    let mut rects: Vec<(i32, i32, i32, i32)> = vec![(0, 0, 0, 0); buildings.len()];
+    let mut street_lengths = vec![0; directions.len()];
+
+    for (k, &i) in districts
+        .iter()
+        .flat_map(|(_, indices)| indices)
+        .enumerate()
+    {
+        let street = k / plots_per_street;
+        let position = k % plots_per_street;
+        let slot = position as i32 / 2;
+        let side = if position % 2 == 0 { 1 } else { -1 };
+
+        let (dx, dy) = directions[street];
+        let (px, py) = (-dy * side, dx * side);
+
+        let along = SQUARE_HALF_SIZE + slot * FRONTAGE;
+        let corner1 = (
+            dx * along + px * STREET_HALF_WIDTH,
+            dy * along + py * STREET_HALF_WIDTH,
+        );
+        let corner2 = (
+            dx * (along + FRONTAGE) + px * (STREET_HALF_WIDTH + DEPTH),
+            dy * (along + FRONTAGE) + py * (STREET_HALF_WIDTH + DEPTH),
+        );
+
+        rects[i] = (
+            corner1.0.min(corner2.0),
+            corner1.1.min(corner2.1),
+            corner1.0.max(corner2.0),
+            corner1.1.max(corner2.1),
+        );
+        street_lengths[street] = street_lengths[street].max((slot + 1) * FRONTAGE);
+
+        let house_number = slot * 2 + if side == 1 { 1 } else { 2 };
+        buildings[i].street = street_names[street].clone();
+        buildings[i].address = format!("{} {}", house_number, street_names[street]);
+    }
+
+    // Shift everything so the top left corner of the town is at 0, 0
+    let min_x = rects
+        .iter()
+        .map(|r| r.0)
+        .chain([-SQUARE_HALF_SIZE])
+        .min()
+        .unwrap();
+    let min_y = rects
+        .iter()
+        .map(|r| r.1)
+        .chain([-SQUARE_HALF_SIZE])
+        .min()
+        .unwrap();
+    let max_x = rects
+        .iter()
+        .map(|r| r.2)
+        .chain([SQUARE_HALF_SIZE])
+        .max()
+        .unwrap();
+    let max_y = rects
+        .iter()
+        .map(|r| r.3)
+        .chain([SQUARE_HALF_SIZE])
+        .max()
+        .unwrap();
+
+    let shift = |x: i32, y: i32| ((x - min_x) as u32, (y - min_y) as u32);
+
+    for (building, rect) in buildings.iter_mut().zip(&rects) {
+        let (x, y) = shift(rect.0, rect.1);
+        let (width, height) = ((rect.2 - rect.0) as u32, (rect.3 - rect.1) as u32);
+
+        building.plot = Plot {
+            x,
+            y,
+            width,
+            height,
+        };
+        building.coords = (x + width / 2, y + height / 2);
+    }
+
+    let streets = directions
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| street_lengths[i] > 0)
+        .map(|(i, &(dx, dy))| {
+            let end = SQUARE_HALF_SIZE + street_lengths[i];
+            let districts = districts
+                .iter()
+                .filter(|(_, indices)| {
+                    indices
+                        .iter()
+                        .any(|&b| buildings[b].street == street_names[i])
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+
+            Street {
+                name: street_names[i].clone(),
+                start: shift(dx * SQUARE_HALF_SIZE, dy * SQUARE_HALF_SIZE),
+                end: shift(dx * end, dy * end),
+                width: (STREET_HALF_WIDTH * 2) as u32,
+                districts,
+            }
+        })
+        .collect();
+
+    let districts = districts
+        .iter()
+        .map(|(name, indices)| District {
+            name: name.clone(),
+            streets: indices
+                .iter()
+                .map(|&i| buildings[i].street.clone())
+                .unique()
+                .collect(),
+            buildings: indices.iter().map(|&i| buildings[i].id).collect(),
+        })
+        .collect();
+
+    let (square_x, square_y) = shift(-SQUARE_HALF_SIZE, -SQUARE_HALF_SIZE);
+
+    TownLayout {
+        width: (max_x - min_x) as u32,
+        height: (max_y - min_y) as u32,
+        square: Square {
+            name: square_name,
+            plot: Plot {
+                x: square_x,
+                y: square_y,
+                width: (SQUARE_HALF_SIZE * 2) as u32,
+                height: (SQUARE_HALF_SIZE * 2) as u32,
+            },
+        },
+        streets,
+        districts,
+    }
+}
+
+// Generate a town name using the grammar, by default a prefix-root-suffix combination
+fn generate_town_name(
+    rng: &mut StdRng,
+    grammar: &Grammar,
+    culture: Option<&CultureConfig>,
+    biome: &BiomeConfig,
+    names: &TownNames,
+) -> String {
+    let symbols = HashMap::from([
+        ("prefix", GrammarSymbol::List(&names.prefixes)),
+        ("root", GrammarSymbol::Names(&names.roots)),
+        ("suffix", GrammarSymbol::List(&names.suffixes)),
+    ]);
+
+    let tags: Vec<String> = culture
+        .map(|culture| culture.name.to_lowercase())
+        .into_iter()
+        .chain([biome.name.to_lowercase()])
+        .collect();
+
+    grammar.generate(rng, "town", &symbols, &tags)
+}
+
+// Function to make a name unique by numbering it if it's already used, e.g. "Oakford 2". Places are saved
+// by name in DOT files, so places with the same name would be merged when importing
+fn unique_name(name: String, used_names: &mut HashSet<String>) -> String {
+    let mut unique = name.clone();
+    let mut number = 1;
+    while used_names.contains(&unique) {
+        number += 1;
+        unique = format!("{} {}", name, number);
+    }
+    used_names.insert(unique.clone());
+
+    unique
+}
+
+// Function to generate buildings
+#[allow(clippy::too_many_arguments)]
+fn generate_buildings(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+    lists: &NameLists,
+    grammar: &Grammar,
+    town_id: &u32,
+    town_culture: Option<&CultureConfig>,
+    town_size: &TownSizeConfig,
+    biome: &BiomeConfig,
+    number_of_buildings: u32,
+) -> Result<Vec<Building>, std::io::Error> {
+    let building_types =
+        generate_building_types(settings, rng, town_size, biome, number_of_buildings)?;
+
+    Ok(generate_buildings_of_types(
+        settings,
+        rng,
+        id_tracker,
+        lists,
+        grammar,
+        town_id,
+        town_culture,
+        building_types,
+        town_size.extra_floors,
+    ))
+}
+
+// Function to generate a building of each of the given types
+#[allow(clippy::too_many_arguments)]
+fn generate_buildings_of_types(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+    lists: &NameLists,
+    grammar: &Grammar,
+    town_id: &u32,
+    town_culture: Option<&CultureConfig>,
+    building_types: Vec<&BuildingTypeConfig>,
+    extra_floors: u32,
+) -> Vec<Building> {
+    let mut buildings = Vec::new();
+
+    let demographics = generate_demographics(settings, rng);
+
+    for type_config in building_types {
+        let building_id = id_tracker.get_new_id(settings);
+
+        // Race and family name of the household or owner, shared by the building and its NPCs
+        let household_race = generate_race(rng, &demographics);
+        let family_name = lists.race_names[&race_key(&household_race)]
+            .surnames
+            .choose(rng, &name_tags(&household_race, None, town_culture))
+            .unwrap_or_else(|| "NO DATA".into());
+
+        let mut building = Building {
+            id: building_id,
+            name: generate_building_name(
+                rng,
+                grammar,
+                type_config,
+                &family_name,
+                &lists.shops,
+                &lists.taverns,
+                &lists.temples,
+            ),
+            building_type: BuildingType(type_config.name.clone()),
+            town_id: *town_id,
+            coords: (0, 0),
+            district: type_config.district.clone(),
+            street: String::new(),
+            address: String::new(),
+            plot: Plot::default(),
+            rooms: Vec::new(),
+            dungeon: None,
+            floors: Vec::new(),
+        };
+
+        let mut npcs = generate_npcs(
+            settings,
+            rng,
+            id_tracker,
+            town_id,
+            &building_id,
+            grammar,
+            &lists.race_names,
+            town_culture,
+            &building.name,
+            type_config,
+            &demographics,
+            &household_race,
+            &family_name,
+        );
+
+        (building.rooms, building.floors) = generate_rooms(
+            settings,
+            rng,
+            id_tracker,
+            town_id,
+            &building_id,
+            type_config,
+            extra_floors,
+            &mut npcs,
+        );
+
+        if type_config.dungeon {
+            building.dungeon = generate_dungeon(settings, rng, &mut building);
+        }
+
+        buildings.push(building);
+    }
+
+    buildings
+}
+
+// Pick the types of a town's buildings so that they satisfy the building types' counts, the town size's
+// services and the constraints, returns an error if that isn't possible. The town's biome makes some
+// building types more or less common
+fn generate_building_types<'a>(
+    settings: &'a AppConfig,
+    rng: &mut StdRng,
+    town_size: &TownSizeConfig,
+    biome: &BiomeConfig,
+    number_of_buildings: u32,
+) -> Result<Vec<&'a BuildingTypeConfig>, std::io::Error> {
+    let types = &settings.building_types;
+
+    let index_of = |name: &str| {
+        types
+            .iter()
+            .position(|type_config| type_config.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| constraint_error(format!("unknown building type \"{}\"", name)))
+    };
+
+    let mut min_counts: Vec<u32> = types
+        .iter()
+        .map(|type_config| {
+            type_config
+                .min_count
+                .max(town_size.service_count(&type_config.name))
+        })
+        .collect();
+    let mut max_counts: Vec<u32> = types
+        .iter()
+        .map(|type_config| type_config.max_count.unwrap_or(u32::MAX))
+        .collect();
+    let mut requirements: Vec<(usize, usize)> = Vec::new();
+
+    for constraint in settings
+        .constraints
+        .iter()
+        .filter(|constraint| constraint.applies_to(town_size))
+    {
+        let index = index_of(&constraint.building_type)?;
+
+        if let Some(min) = constraint.min {
+            min_counts[index] = min_counts[index].max(constraint.scale(min, number_of_buildings));
+        }
+        if let Some(max) = constraint.max {
+            max_counts[index] = max_counts[index].min(constraint.scale(max, number_of_buildings));
+        }
+        if let Some(required) = &constraint.requires {
+            requirements.push((index, index_of(required)?));
+        }
+    }
+
+    // Start with the minimums plus whatever they require
+    let mut counts = add_required_buildings(&min_counts, &requirements);
+
+    for (i, type_config) in types.iter().enumerate() {
+        if counts[i] > max_counts[i] {
+            return Err(constraint_error(format!(
+                "a {} of {} buildings needs at least {} {} buildings but at most {} are allowed",
+                town_size.name, number_of_buildings, counts[i], type_config.name, max_counts[i]
+            )));
+        }
+    }
+
+    let mut total: u32 = counts.iter().sum();
+    if total > number_of_buildings {
+        return Err(constraint_error(format!(
+            "a {} of {} buildings can't fit the {} buildings required by the constraints",
+            town_size.name, number_of_buildings, total
+        )));
+    }
+
+    // Fill the rest of the town, adding a building also adds the buildings it requires
+    while total < number_of_buildings {
+        let candidates: Vec<(usize, Vec<u32>)> = types
+            .iter()
+            .enumerate()
+            .filter_map(|(i, _)| {
+                let mut next = counts.clone();
+                next[i] += 1;
+                let next = add_required_buildings(&next, &requirements);
+
+                let fits = next.iter().sum::<u32>() <= number_of_buildings
+                    && next
+                        .iter()
+                        .zip(&max_counts)
+                        .all(|(count, max)| count <= max);
+
+                fits.then_some((i, next))
+            })
+            .collect();
+
+        match candidates.choose_weighted(rng, |(i, _)| {
+            types[*i].weight * biome.building_weight(&types[*i].name)
+        }) {
+            Ok((_, next)) => {
+                counts = next.clone();
+                total = counts.iter().sum();
+            }
+            Err(_) => {
+                return Err(constraint_error(format!(
+                    "only {} of the {} buildings of a {} could be placed without breaking the constraints",
+                    total, number_of_buildings, town_size.name
+                )));
+            }
+        }
+    }
+
+    let mut building_types: Vec<&BuildingTypeConfig> = types
+        .iter()
+        .zip(&counts)
+        .flat_map(|(type_config, &count)| std::iter::repeat_n(type_config, count as usize))
+        .collect();
+
+    building_types.shuffle(rng);
+
+    Ok(building_types)
+}
+
+// Raise building counts until every building type has at least as many of the buildings it requires
+fn add_required_buildings(counts: &[u32], requirements: &[(usize, usize)]) -> Vec<u32> {
+    let mut counts = counts.to_vec();
+
+    // Chains of requirements settle within one pass per building type
+    for _ in 0..=counts.len() {
+        let mut changed = false;
+
+        for &(building, required) in requirements {
+            if counts[required] < counts[building] {
+                counts[required] = counts[building];
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    counts
+}
+
+// Function to create an error for building constraints that can't be satisfied
+fn constraint_error(message: String) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("Unable to satisfy building constraints: {}", message),
+    )
+}
+
+// Generate a building name using the building type's name template
+fn generate_building_name(
+    rng: &mut StdRng,
+    grammar: &Grammar,
+    type_config: &BuildingTypeConfig,
+    family_name: &str,
+    shops: &[ListEntry],
+    taverns: &[ListEntry],
+    temples: &[ListEntry],
+) -> String {
+    let symbols = HashMap::from([
+        ("surname", GrammarSymbol::Text(family_name.to_string())),
+        ("shop_type", GrammarSymbol::List(shops)),
+        ("tavern_name", GrammarSymbol::List(taverns)),
+        ("temple_dedication", GrammarSymbol::List(temples)),
+    ]);
+
+    match &type_config.name_template {
+        Some(template) => grammar.generate_from_template(rng, template, &symbols, &[]),
+        None => grammar.generate(rng, &type_config.key(), &symbols, &[]),
+    }
+}
+
+// Function for loading in the name lists of every race's naming culture
+fn load_race_names(settings: &AppConfig) -> HashMap<String, CultureNames> {
+    settings
+        .races
+        .iter()
+        .map(|race| {
+            let names = load_culture_names(settings, culture_for_race(settings, race));
+            (race.name.to_lowercase(), names)
+        })
+        .collect()
+}
+
+// Function to get the key used to look up a race's naming culture
+fn race_key(race: &NpcRace) -> String {
+    race.0.to_lowercase()
+}
+
+// Function to get the tags used to pick names suited to an NPC from annotated lists
+fn name_tags(
+    race: &NpcRace,
+    sex: Option<&NpcSex>,
+    town_culture: Option<&CultureConfig>,
+) -> Vec<String> {
+    let mut tags = vec![race_key(race)];
+
+    if let Some(sex) = sex {
+        tags.push(format!("{:?}", sex).to_lowercase());
+    }
+    if let Some(culture) = town_culture {
+        tags.push(culture.name.to_lowercase());
+    }
+
+    tags
+}
+
+// Generate a town's demographics, each race's weight varies from town to town by up to its variance
+fn generate_demographics(settings: &AppConfig, rng: &mut StdRng) -> Vec<(NpcRace, f32)> {
+    settings
+        .races
+        .iter()
+        .map(|race| {
+            let variance = race.variance.abs();
+            let factor = if variance > 0.0 {
+                1.0 + rng.gen_range(-variance..=variance)
+            } else {
+                1.0
+            };
+
+            (NpcRace(race.name.clone()), (race.weight * factor).max(0.0))
+        })
+        .collect()
+}
+
+// Generate a random race based on a town's demographics
+fn generate_race(rng: &mut StdRng, demographics: &[(NpcRace, f32)]) -> NpcRace {
+    match demographics.choose_weighted(rng, |(_, weight)| *weight) {
+        Ok((race, _)) => race.clone(),
+        Err(_) => demographics[rng.gen_range(0..demographics.len())].0.clone(),
+    }
+}
+
+// Generate an NPC's age within their race's lifespan
+fn generate_age(settings: &AppConfig, rng: &mut StdRng, race: &NpcRace) -> u32 {
+    match settings.races.iter().find(|config| config.name == race.0) {
+        Some(config) => rng.gen_range(config.min_age..=config.max_age.max(config.min_age)),
+        None => 0,
+    }
+}
+
+// Generate NPCs
+#[allow(clippy::too_many_arguments)]
+fn generate_npcs(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+    town_id: &u32,
+    building_id: &u32,
+    grammar: &Grammar,
+    race_names: &HashMap<String, CultureNames>,
+    town_culture: Option<&CultureConfig>,
+    building_name: &str,
+    type_config: &BuildingTypeConfig,
+    demographics: &[(NpcRace, f32)],
+    household_race: &NpcRace,
+    family_name: &str,
+) -> Vec<Npc> {
+    let mut npcs = Vec::new();
+
+    let number_of_npcs = match (type_config.min_npcs, type_config.max_npcs) {
+        (Some(min), Some(max)) => rng.gen_range(min..=max.max(min)),
+        (Some(min), None) => rng.gen_range(min..=settings.max_npcs.max(min)),
+        (None, Some(max)) => rng.gen_range(settings.min_npcs.min(max)..=max),
+        (None, None) => rng.gen_range(settings.min_npcs..=settings.max_npcs.max(settings.min_npcs)),
+    };
+
+    for _ in 0..number_of_npcs {
+        let npc_id = id_tracker.get_new_id(settings);
+
+        let sex = match rng.gen_range(0..NpcSex::COUNT) {
+            0 => NpcSex::Male,
+            1 => NpcSex::Female,
+            2 => NpcSex::Unisex,
+            _ => NpcSex::Unisex,
+        };
+
+        // Households share a race, everyone else is random
+        let race = if type_config.family {
+            household_race.clone()
+        } else {
+            generate_race(rng, demographics)
+        };
+
+        let age = generate_age(settings, rng, &race);
+
+        let npc_tags = name_tags(&race, Some(&sex), town_culture);
+
+        npcs.push(Npc {
+            id: npc_id,
+            name: generate_npc_name(
+                rng,
+                grammar,
+                &race_names[&race_key(&race)],
+                building_name,
+                type_config,
+                family_name,
+                &sex,
+                &npc_tags,
+            ),
+            sex,
+            race,
+            age,
+            town_id: *town_id,
+            building_id: *building_id,
+            room_id: None,
+        });
+    }
+
+    npcs
+}
+
+// Generate an NPC name using the grammar rule for the NPC's building type
+#[allow(clippy::too_many_arguments)]
+fn generate_npc_name(
+    rng: &mut StdRng,
+    grammar: &Grammar,
+    names: &CultureNames,
+    building_name: &str,
+    type_config: &BuildingTypeConfig,
+    family_name: &str,
+    npc_sex: &NpcSex,
+    npc_tags: &[String],
+) -> String {
+    // Family buildings share the family name, everyone else gets their own
+    let surname = if type_config.family {
+        GrammarSymbol::Text(family_name.to_string())
+    } else {
+        GrammarSymbol::Names(&names.surnames)
+    };
+
+    let symbols = HashMap::from([
+        (
+            "firstname",
+            GrammarSymbol::Names(names.first_names(npc_sex)),
+        ),
+        ("surname", surname),
+        ("building", GrammarSymbol::Text(building_name.to_string())),
+    ]);
+
+    // Use the building type's NPC rule if there is one, otherwise the generic one
+    let rule = format!("npc_{}", type_config.key());
+    let rule = if grammar.has_rule(&rule) {
+        rule
+    } else {
+        "npc".into()
+    };
+
+    grammar.generate(rng, &rule, &symbols, npc_tags)
+}
+
+// Generate rooms and the floors they're on
+#[allow(clippy::too_many_arguments)]
+fn generate_rooms(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+    town_id: &u32,
+    building_id: &u32,
+    type_config: &BuildingTypeConfig,
+    extra_floors: u32,
+    npcs: &mut Vec<Npc>,
+) -> (Vec<Room>, Vec<Floor>) {
+    let mut rooms = Vec::new();
+
+    let number_of_rooms = match (type_config.min_rooms, type_config.max_rooms) {
+        (Some(min), Some(max)) => rng.gen_range(min..=max.max(min)),
+        (Some(min), None) => rng.gen_range(min..=settings.max_rooms.max(min)),
+        (None, Some(max)) => rng.gen_range(settings.min_rooms.min(max)..=max),
+        (None, None) => {
+            rng.gen_range(settings.min_rooms..=settings.max_rooms.max(settings.min_rooms))
+        }
+    };
+
+    for _ in 0..number_of_rooms {
+        let room_id = id_tracker.get_new_id(settings);
+
+        rooms.push(Room {
+            id: room_id,
+            town_id: *town_id,
+            building_id: *building_id,
+            npcs: Vec::new(),
+            containers: Vec::new(),
+            purpose: String::new(),
+            floor: 0,
+            role: RoomRole::Chamber,
+            exits: Vec::new(),
+        });
+    }
+
+    if rooms.is_empty() {
+        return (rooms, Vec::new());
+    }
+
+    // Dungeons are laid out by generate_dungeon, all on one floor, which also places their containers and
+    // NPCs and names their rooms
+    if type_config.dungeon && rooms.len() >= Dungeon::MIN_ROOMS {
+        for room in &mut rooms {
+            room.containers = generate_containers(
+                settings,
+                rng,
+                id_tracker,
+                town_id,
+                building_id,
+                &room.id,
+                &type_config.containers,
+                None,
+            );
+        }
+        rooms[0].npcs.append(npcs);
+        let floors = vec![Floor {
+            level: 0,
+            kind: FloorKind::Ground,
+            rooms: rooms.iter().map(|room| room.id).collect(),
+        }];
+
+        return (rooms, floors);
+    }
+
+    let floor_kinds = generate_floors(rng, type_config, extra_floors, number_of_rooms);
+    let ground = floor_kinds
+        .iter()
+        .position(|&kind| kind == FloorKind::Ground)
+        .unwrap_or_default();
+
+    // Every floor has a room, the rest go to the ground and upper floors in turn. The ground floor's rooms
+    // come first, so the first room is the entrance
+    let mut sizes = vec![1; floor_kinds.len()];
+    let main_floors: Vec<usize> = (0..floor_kinds.len())
+        .filter(|&i| matches!(floor_kinds[i], FloorKind::Ground | FloorKind::Upper))
+        .collect();
+    for i in 0..rooms.len().saturating_sub(floor_kinds.len()) {
+        sizes[main_floors[i % main_floors.len()]] += 1;
+    }
+    let mut next_room = 0;
+    let mut storeys = vec![Vec::new(); floor_kinds.len()];
+    for level in (ground..floor_kinds.len()).chain(0..ground) {
+        storeys[level] = (next_room..next_room + sizes[level]).collect();
+        next_room += sizes[level];
+    }
+
+    let mut floors = Vec::new();
+    let mut room_floors = vec![FloorKind::Ground; rooms.len()];
+    for (level, storey) in storeys.iter().enumerate() {
+        for &room in storey {
+            rooms[room].floor = level as i32 - ground as i32;
+            room_floors[room] = floor_kinds[level];
+        }
+        floors.push(Floor {
+            level: level as i32 - ground as i32,
+            kind: floor_kinds[level],
+            rooms: storey.iter().map(|&room| rooms[room].id).collect(),
+        });
+    }
+    connect_rooms(settings, rng, &mut rooms, &storeys, ground);
+
+    // Rooms without a room type are named after their role, or their floor if it's a cellar or attic
+    let room_types = choose_room_types(rng, type_config, &rooms, &room_floors);
+    for ((room, room_type), floor) in rooms.iter_mut().zip(&room_types).zip(&room_floors) {
+        room.purpose = match room_type {
+            Some(room_type) => room_type.name.clone(),
+            None if room.role == RoomRole::Chamber
+                && matches!(floor, FloorKind::Cellar | FloorKind::Attic) =>
+            {
+                format!("{:?}", floor)
+            }
+            None => format!("{:?}", room.role),
+        };
+        room.containers = generate_containers(
+            settings,
+            rng,
+            id_tracker,
+            town_id,
+            building_id,
+            &room.id,
+            room_type
+                .and_then(|room_type| room_type.containers.as_deref())
+                .unwrap_or(&type_config.containers),
+            room_type.map(|room_type| (room_type.min_containers, room_type.max_containers)),
+        );
+    }
+
+    npcs.shuffle(rng);
+
+    // NPCs are found in rooms by their room type's NPC weight, at the entrance if no room has any
+    let npc_weights: Vec<f32> = room_types
+        .iter()
+        .map(|room_type| room_type.map_or(1.0, |room_type| room_type.npc_weight))
+        .collect();
+    let indices: Vec<usize> = (0..rooms.len()).collect();
+    let entrance = rooms
+        .iter()
+        .position(|room| room.role == RoomRole::Entrance)
+        .or(indices.first().copied());
+    for mut npc in npcs.drain(..) {
+        let index = match indices.choose_weighted(rng, |&i| npc_weights[i]) {
+            Ok(&index) => Some(index),
+            Err(_) => entrance,
+        };
+        if let Some(room) = index.map(|index| &mut rooms[index]) {
+            npc.room_id = Some(room.id);
+            room.npcs.push(npc);
+        }
+    }
+
+    (rooms, floors)
+}
+
+// Pick the floors of a building from bottom to top. Buildings of types with upper floors get the town size's
+// extra floors as well, and every floor needs a room, so small buildings lose their attic first, then upper
+// floors, then their cellar. Dungeons are all on one floor
+fn generate_floors(
+    rng: &mut StdRng,
+    type_config: &BuildingTypeConfig,
+    extra_floors: u32,
+    number_of_rooms: u32,
+) -> Vec<FloorKind> {
+    if type_config.dungeon {
+        return vec![FloorKind::Ground];
+    }
+
+    let mut upper_floors = rng.gen_range(
+        type_config.min_upper_floors
+            ..=type_config
+                .max_upper_floors
+                .max(type_config.min_upper_floors),
+    );
+    if type_config.max_upper_floors > 0 {
+        upper_floors += extra_floors;
+    }
+    let cellar = rng.gen_bool(type_config.cellar_chance.clamp(0.0, 1.0));
+    let attic = rng.gen_bool(type_config.attic_chance.clamp(0.0, 1.0));
+
+    let mut rooms_left = number_of_rooms.saturating_sub(1);
+    let cellar = cellar && rooms_left > 0;
+    rooms_left -= cellar as u32;
+    let upper_floors = upper_floors.min(rooms_left);
+    rooms_left -= upper_floors;
+    let attic = attic && rooms_left > 0;
+
+    let mut floors = Vec::new();
+    if cellar {
+        floors.push(FloorKind::Cellar);
+    }
+    floors.push(FloorKind::Ground);
+    floors.extend(std::iter::repeat_n(FloorKind::Upper, upper_floors as usize));
+    if attic {
+        floors.push(FloorKind::Attic);
+    }
+
+    floors
+}
+
+// Pick a room type for each room from its building type's room types for the room's role and floor. Room
+// types with a minimum count are picked first, in the order they're listed, then the rest by weight up to
+// their maximum
+fn choose_room_types<'a>(
+    rng: &mut StdRng,
+    type_config: &'a BuildingTypeConfig,
+    rooms: &[Room],
+    floors: &[FloorKind],
+) -> Vec<Option<&'a RoomTypeConfig>> {
+    let mut required: Vec<u32> = type_config
+        .room_types
+        .iter()
+        .map(|room_type| room_type.min_count)
+        .collect();
+    let mut counts: HashMap<&str, u32> = HashMap::new();
+
+    rooms
+        .iter()
+        .zip(floors)
+        .map(|(room, &floor)| {
+            let fits =
+                |room_type: &RoomTypeConfig| room_type.role == room.role && room_type.allows(floor);
+
+            let room_type = match (0..required.len())
+                .find(|&i| required[i] > 0 && fits(&type_config.room_types[i]))
+            {
+                Some(i) => {
+                    required[i] -= 1;
+                    Some(&type_config.room_types[i])
+                }
+                None => {
+                    let available: Vec<&RoomTypeConfig> = type_config
+                        .room_types
+                        .iter()
+                        .filter(|room_type| {
+                            fits(room_type)
+                                && room_type.max_count.is_none_or(|max| {
+                                    counts.get(room_type.name.as_str()).copied().unwrap_or(0) < max
+                                })
+                        })
+                        .collect();
+                    available
+                        .choose_weighted(rng, |room_type| room_type.weight)
+                        .ok()
+                        .copied()
+                }
+            };
+
+            if let Some(room_type) = room_type {
+                *counts.entry(room_type.name.as_str()).or_default() += 1;
+            }
+            room_type
+        })
+        .collect()
+}
+
+// Connect a building's rooms, storey by storey from the bottom. The first room of the ground storey is the
+// entrance, and the first of every other storey is reached by stairs from the storey next to it on the way to
+// the ground, a landing if the storey has more than one room. Storeys with enough rooms have a hallway off the
+// entrance or landing, and the other rooms open off it or off each other, so every room can be reached from
+// the entrance
+fn connect_rooms(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    rooms: &mut [Room],
+    storeys: &[Vec<usize>],
+    ground: usize,
+) {
+    // How often a room leads on from another room rather than opening off the hallway
+    const LEAD_ON_CHANCE: f64 = 0.3;
+
+    let archway_chance = settings.archway_chance.clamp(0.0, 1.0);
+    let lock_chance = settings.door_lock_chance.clamp(0.0, 1.0);
+
+    let mut graph: Graph<usize, ExitKind, Undirected> = Graph::new_undirected();
+    let nodes: Vec<NodeIndex> = (0..rooms.len()).map(|i| graph.add_node(i)).collect();
+    let mut locks = HashSet::new();
+
+    // Storeys are connected outwards from the ground, to the hub of the storey before them
+    let mut hubs: Vec<Option<usize>> = vec![None; storeys.len()];
+    for level in (ground..storeys.len()).chain((0..ground).rev()) {
+        let storey = &storeys[level];
+        let Some((&first, rest)) = storey.split_first() else {
+            continue;
+        };
+
+        rooms[first].role = if level == ground {
+            RoomRole::Entrance
+        } else if storey.len() > 1 {
+            RoomRole::Hallway
+        } else {
+            RoomRole::Chamber
+        };
+        let linked = match level.cmp(&ground) {
+            std::cmp::Ordering::Greater => hubs[level - 1],
+            std::cmp::Ordering::Less => hubs[level + 1],
+            std::cmp::Ordering::Equal => None,
+        };
+        if let Some(linked) = linked {
+            graph.add_edge(nodes[linked], nodes[first], ExitKind::Stairs);
+        }
+
+        let (hub, rest) = match rest.split_first() {
+            Some((&hallway, others)) if storey.len() as u32 >= settings.hallway_min_rooms => {
+                rooms[hallway].role = RoomRole::Hallway;
+                graph.add_edge(nodes[first], nodes[hallway], ExitKind::Door);
+                (hallway, others)
+            }
+            _ => (first, rest),
+        };
+
+        // Most rooms open off the hallway, some lead on from another room
+        let mut connected: Vec<usize> = Vec::new();
+        for &room in rest {
+            let from = match connected.choose(rng) {
+                Some(&other) if rng.gen_bool(LEAD_ON_CHANCE) => other,
+                _ => hub,
+            };
+            let kind = if rng.gen_bool(archway_chance) {
+                ExitKind::Archway
+            } else {
+                ExitKind::Door
+            };
+            let edge = graph.add_edge(nodes[from], nodes[room], kind);
+            if kind == ExitKind::Door && rng.gen_bool(lock_chance) {
+                locks.insert(edge);
+            }
+            connected.push(room);
+        }
+
+        hubs[level] = Some(hub);
+    }
+
+    for edge in graph.edge_references() {
+        let (a, b) = (graph[edge.source()], graph[edge.target()]);
+        let locked = locks.contains(&edge.id());
+        let (a_id, b_id) = (rooms[a].id, rooms[b].id);
+        rooms[a].exits.push(Exit {
+            room_id: b_id,
+            kind: *edge.weight(),
+            locked,
+        });
+        rooms[b].exits.push(Exit {
+            room_id: a_id,
+            kind: *edge.weight(),
+            locked,
+        });
+    }
+}
+
+// Generate a dungeon from a building's rooms. Each room branches off an earlier one, more often off deeper
+// ones, with a few loops between rooms of about the same depth. The deepest room is the boss room, behind
+// locked doors, and the building's containers and NPCs are moved deeper the further they can be
+fn generate_dungeon(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    building: &mut Building,
+) -> Option<Dungeon> {
+    if building.rooms.len() < Dungeon::MIN_ROOMS {
+        return None;
+    }
+    let entrance = building.rooms[0].id;
+    let lock_chance = settings.dungeon_lock_chance.clamp(0.0, 1.0);
+    let loop_chance = settings.dungeon_loop_chance.clamp(0.0, 1.0);
+
+    let mut graph: Graph<u32, bool, Undirected> = Graph::new_undirected();
+    let nodes: Vec<NodeIndex> = building
+        .rooms
+        .iter()
+        .map(|room| graph.add_node(room.id))
+        .collect();
+    let mut depths: Vec<u32> = vec![0; nodes.len()];
+
+    for i in 1..nodes.len() {
+        let parent = *(0..i)
+            .collect::<Vec<usize>>()
+            .choose_weighted(rng, |&j| depths[j] + 1)
+            .ok()?;
+        depths[i] = depths[parent] + 1;
+        graph.add_edge(nodes[parent], nodes[i], rng.gen_bool(lock_chance));
+    }
+
+    for (i, j) in (1..nodes.len()).tuple_combinations() {
+        if depths[i].abs_diff(depths[j]) <= 1
+            && !graph.contains_edge(nodes[i], nodes[j])
+            && rng.gen_bool(loop_chance)
+        {
+            graph.add_edge(nodes[i], nodes[j], false);
+        }
+    }
+
+    // The boss room is only reached through locked doors
+    let boss = (1..nodes.len()).max_by_key(|&i| depths[i]).unwrap_or(0);
+    let boss_edges: Vec<_> = graph.edges(nodes[boss]).map(|edge| edge.id()).collect();
+    for edge in boss_edges {
+        graph[edge] = true;
+    }
+
+    let kinds: Vec<DungeonRoomKind> = (0..nodes.len())
+        .map(|i| match graph.edges(nodes[i]).count() {
+            _ if i == 0 => DungeonRoomKind::Entrance,
+            _ if i == boss => DungeonRoomKind::BossRoom,
+            1 => DungeonRoomKind::DeadEnd,
+            2 => DungeonRoomKind::Corridor,
+            _ => DungeonRoomKind::Chamber,
+        })
+        .collect();
+
+    // The first NPC guards the boss room, and containers are never left in corridors
+    let mut npcs: Vec<Npc> = building
+        .rooms
+        .iter_mut()
+        .flat_map(|room| room.npcs.drain(..))
+        .collect();
+    let mut containers: Vec<Container> = building
+        .rooms
+        .iter_mut()
+        .flat_map(|room| room.containers.drain(..))
+        .collect();
+    let boss_npc = (!npcs.is_empty()).then(|| npcs.remove(0));
+
+    let depth_weight = |i: usize| match kinds[i] {
+        DungeonRoomKind::DeadEnd | DungeonRoomKind::BossRoom => 2 * (depths[i] + 1),
+        _ => depths[i] + 1,
+    };
+    let other_rooms: Vec<usize> = (0..nodes.len()).filter(|&i| i != boss).collect();
+    let container_rooms: Vec<usize> = (0..nodes.len())
+        .filter(|&i| kinds[i] != DungeonRoomKind::Corridor)
+        .collect();
+
+    if let Some(npc) = boss_npc.clone() {
+        building.rooms[boss].npcs.push(npc);
+    }
+    for npc in npcs {
+        if let Ok(&i) = other_rooms.choose_weighted(rng, |&i| depth_weight(i)) {
+            building.rooms[i].npcs.push(npc);
+        }
+    }
+    if !containers.is_empty() {
+        building.rooms[boss].containers.push(containers.remove(0));
+    }
+    for container in containers {
+        if let Ok(&i) = container_rooms.choose_weighted(rng, |&i| depth_weight(i)) {
+            building.rooms[i].containers.push(container);
+        }
+    }
+    for (i, room) in building.rooms.iter_mut().enumerate() {
+        let room_id = room.id;
+        room.role = match kinds[i] {
+            DungeonRoomKind::Entrance => RoomRole::Entrance,
+            DungeonRoomKind::Corridor => RoomRole::Hallway,
+            _ => RoomRole::Chamber,
+        };
+        room.purpose = kinds[i].name().into();
+        room.exits = graph
+            .edges(nodes[i])
+            .map(|edge| Exit {
+                room_id: graph[if edge.source() == nodes[i] {
+                    edge.target()
+                } else {
+                    edge.source()
+                }],
+                kind: ExitKind::Door,
+                locked: *edge.weight(),
+            })
+            .collect();
+        room.npcs
+            .iter_mut()
+            .for_each(|npc| npc.room_id = Some(room_id));
+        room.containers
+            .iter_mut()
+            .for_each(|container| container.room_id = room_id);
+    }
+
+    let passages = graph
+        .edge_references()
+        .map(|edge| {
+            let (from, to) = if depths[edge.source().index()] <= depths[edge.target().index()] {
+                (edge.source(), edge.target())
+            } else {
+                (edge.target(), edge.source())
+            };
+            Passage {
+                from: graph[from],
+                to: graph[to],
+                locked: *edge.weight(),
+            }
+        })
+        .collect();
+
+    Some(Dungeon {
+        building_id: building.id,
+        town_id: building.town_id,
+        name: building.name.clone(),
+        entrance,
+        boss_room: graph[nodes[boss]],
+        boss: boss_npc.map(|npc| npc.id),
+        rooms: (0..nodes.len())
+            .map(|i| DungeonRoom {
+                room_id: graph[nodes[i]],
+                kind: kinds[i],
+                depth: depths[i],
+            })
+            .collect(),
+        passages,
+    })
+}
+
+// Generate containers, a room type's container range replaces the global one
+#[allow(clippy::too_many_arguments)]
+fn generate_containers(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+    town_id: &u32,
+    building_id: &u32,
+    room_id: &u32,
+    container_types: &[ContainerType],
+    container_range: Option<(Option<u32>, Option<u32>)>,
+) -> Vec<Container> {
+    let mut containers = Vec::new();
+
+    let num_of_containers = match container_range.unwrap_or_default() {
+        (Some(min), Some(max)) => rng.gen_range(min..=max.max(min)),
+        (Some(min), None) => rng.gen_range(min..=settings.max_containers.max(min)),
+        (None, Some(max)) => rng.gen_range(settings.min_containers.min(max)..=max),
+        (None, None) => rng.gen_range(
+            settings.min_containers..=settings.max_containers.max(settings.min_containers),
+        ),
+    };
+
+    for _ in 0..num_of_containers {
+        let container_type = match container_types.choose(rng) {
+            Some(container_type) => container_type.clone(),
+            None => break,
+        };
+
+        let container_id = id_tracker.get_new_id(settings);
+
+        containers.push(Container {
+            id: container_id,
+            container_type,
+            town_id: *town_id,
+            building_id: *building_id,
+            room_id: *room_id,
+        });
+    }
+
+    containers
+}
+
+// Generate a graph with towns and edges using Kruskal’s Algorithm
+fn generate_graph<N>(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    towns: Vec<N>,
+) -> (Graph<N, JourneyInfo>, Vec<NodeIndex>) {
+    print!("Generating graph... ");
+
+    let mut town_graph = Graph::<N, JourneyInfo>::new();
+    let mut town_nodes = Vec::new();
+
+    for town in towns {
+        let node = town_graph.add_node(town);
+        town_nodes.push(node);
+    }
+
+    // Create a lookup table for constant-time index retrieval
+    let node_map: HashMap<NodeIndex, usize> = town_nodes
+        .iter()
+        .enumerate()
+        .map(|(i, &n)| (n, i))
+        .collect();
+
+    let mut town_pairs: Vec<(NodeIndex, NodeIndex, u32)> = town_nodes
+        .iter()
+        .tuple_combinations()
+        .map(|(&t1, &t2)| {
+            let distance = rng.gen_range(
+                settings.min_distance..=settings.max_distance.max(settings.min_distance),
+            );
+            (t1, t2, distance)
+        })
+        .collect();
+
+    town_pairs.sort_unstable_by_key(|&(_, _, dist)| dist);
+
+    let mut uf = UnionFind::new(town_nodes.len());
+    let mut edges_added = 0;
+
+    for (town1, town2, distance) in &town_pairs {
+        let idx1 = *node_map.get(town1).expect("Town1 not found in node_map");
+        let idx2 = *node_map.get(town2).expect("Town2 not found in node_map");
+
+        if uf.union(idx1, idx2) {
+            let cost = settings.cost * distance;
+            town_graph.add_edge(
+                *town1,
+                *town2,
+                JourneyInfo::new(*distance, cost, RouteKind::Road),
+            );
+            edges_added += 1;
+        }
+    }
+
+    for (town1, town2, distance) in town_pairs
+        .into_iter()
+        .skip(edges_added)
+        .take(settings.num_of_connections as usize - edges_added)
+    {
+        let cost = settings.cost * distance;
+        town_graph.add_edge(
+            town1,
+            town2,
+            JourneyInfo::new(distance, cost, RouteKind::Road),
+        );
+    }
+
+    println!("done!");
+
+    (town_graph, town_nodes)
+}
+
+// Generate coordinates for the towns in a graph, placing them so the straight line distance between towns
+// is as close as possible to the distance along the roads (stress majorization)
+fn generate_town_coords<N>(rng: &mut StdRng, graph: &Graph<N, JourneyInfo>) -> Vec<(u32, u32)> {
+    const ITERATIONS: usize = 300;
+    const MARGIN: f64 = 10.0;
+
+    let n = graph.node_count();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // Shortest distances along the roads between every pair of towns (Floyd-Warshall)
+    let mut distances = vec![vec![f64::INFINITY; n]; n];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[i] = 0.0;
+    }
+    for edge in graph.edge_references() {
+        let (a, b) = (edge.source().index(), edge.target().index());
+        let distance = (edge.weight().distance as f64).min(distances[a][b]);
+        distances[a][b] = distance;
+        distances[b][a] = distance;
+    }
+    for k in 0..n {
+        for i in 0..n {
+            for j in 0..n {
+                let through_k = distances[i][k] + distances[k][j];
+                if through_k < distances[i][j] {
+                    distances[i][j] = through_k;
+                }
+            }
+        }
+    }
+
+    // Towns that aren't connected are kept a little further apart than the furthest connected towns
+    let furthest = distances
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|d| d.is_finite())
+        .fold(1.0, f64::max);
+    for distance in distances.iter_mut().flatten() {
+        if !distance.is_finite() {
+            *distance = furthest * 1.5;
+        }
+    }
+
+    let mut positions: Vec<(f64, f64)> = (0..n)
+        .map(|_| (rng.gen_range(0.0..furthest), rng.gen_range(0.0..furthest)))
+        .collect();
+
+    for _ in 0..ITERATIONS {
+        for i in 0..n {
+            let (mut sum_x, mut sum_y, mut sum_weights) = (0.0, 0.0, 0.0);
+
+            for j in (0..n).filter(|&j| j != i && distances[i][j] > 0.0) {
+                let target = distances[i][j];
+                let weight = 1.0 / (target * target);
+                let (dx, dy) = (
+                    positions[i].0 - positions[j].0,
+                    positions[i].1 - positions[j].1,
+                );
+                let length = (dx * dx + dy * dy).sqrt().max(1e-6);
+
+                sum_x += weight * (positions[j].0 + target * dx / length);
+                sum_y += weight * (positions[j].1 + target * dy / length);
+                sum_weights += weight;
+            }
+
+            if sum_weights > 0.0 {
+                positions[i] = (sum_x / sum_weights, sum_y / sum_weights);
+            }
+        }
+    }
+
+    let min_x = positions.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+    let min_y = positions.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+
+    positions
+        .iter()
+        .map(|&(x, y)| {
+            (
+                (x - min_x + MARGIN).round() as u32,
+                (y - min_y + MARGIN).round() as u32,
+            )
+        })
+        .collect()
+}
+
+// Generate the world's terrain and move each town to the most suitable land near its position. The terrain
+// is shifted first so that as many towns as possible start on suitable land
+fn place_towns_on_terrain<'a>(
+    settings: &'a AppConfig,
+    rng: &mut StdRng,
+    coords: &[(u32, u32)],
+) -> (Terrain, TownPlacements<'a>) {
+    const OFFSET_ATTEMPTS: usize = 32;
+    const RIVER_BONUS: f64 = 0.5;
+
+    print!("Generating terrain... ");
+
+    let seed = rng.gen();
+    let scale = settings.terrain_scale.max(1.0);
+
+    // Land by a river is better for a town, as long as it's suitable at all
+    let suitability = |terrain: &Terrain, (x, y): (u32, u32)| {
+        let by_river = terrain
+            .rivers
+            .iter()
+            .flat_map(|river| river.points.iter())
+            .any(|point| point.0.abs_diff(x).max(point.1.abs_diff(y)) <= settings.port_distance);
+
+        match terrain.biome(settings, x as f64, y as f64).suitability {
+            _ if terrain
+                .lakes
+                .iter()
+                .any(|lake| lake.contains(x as f64, y as f64)) =>
+            {
+                0.0
+            }
+            suitability if by_river && suitability > 0.0 => suitability + RIVER_BONUS,
+            suitability => suitability,
+        }
+    };
+
+    let mut terrain = (0..OFFSET_ATTEMPTS)
+        .map(|_| Terrain {
+            seed,
+            scale,
+            offset: (
+                rng.gen_range(0.0..scale * 100.0),
+                rng.gen_range(0.0..scale * 100.0),
+            ),
+            rivers: Vec::new(),
+            lakes: Vec::new(),
+        })
+        .max_by(|a, b| {
+            let total_a: f64 = coords.iter().map(|&point| suitability(a, point)).sum();
+            let total_b: f64 = coords.iter().map(|&point| suitability(b, point)).sum();
+            total_a.total_cmp(&total_b)
+        })
+        .expect("at least one terrain offset is tried");
+
+    generate_waterways(settings, rng, &mut terrain, coords);
+
+    // Nearby land is preferred over land that's just as suitable but further away
+    let radius = settings.town_placement_radius as i64;
+    let placements = coords
+        .iter()
+        .map(|&(x, y)| {
+            let mut best = ((x, y), suitability(&terrain, (x, y)));
+
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let distance = ((dx * dx + dy * dy) as f64).sqrt();
+                    if distance > radius as f64 || x as i64 + dx < 0 || y as i64 + dy < 0 {
+                        continue;
+                    }
+
+                    let point = ((x as i64 + dx) as u32, (y as i64 + dy) as u32);
+                    let score =
+                        suitability(&terrain, point) * (1.0 - 0.25 * distance / radius as f64);
+                    if score > best.1 {
+                        best = (point, score);
+                    }
+                }
+            }
+
+            let (point, _) = best;
+            (
+                point,
+                terrain.biome(settings, point.0 as f64, point.1 as f64),
+            )
+        })
+        .collect();
+
+    println!("done!");
+
+    (terrain, placements)
+}
+
+// Trace rivers downhill from high ground around the towns until they reach water, join another river or
+// run off the map. Rivers that end in a hollow fill it with a lake
+fn generate_waterways(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    terrain: &mut Terrain,
+    coords: &[(u32, u32)],
+) {
+    const MARGIN: f64 = 20.0;
+    const STEP: f64 = 2.0;
+    const MAX_STEPS: usize = 500;
+    const ATTEMPTS_PER_RIVER: u32 = 50;
+    const SOURCE_ELEVATION: (f64, f64) = (0.65, 0.95);
+    const LAKE_RADIUS: u32 = 4;
+
+    let left = (coords.iter().map(|c| c.0).min().unwrap_or_default() as f64 - MARGIN).max(0.0);
+    let top = (coords.iter().map(|c| c.1).min().unwrap_or_default() as f64 - MARGIN).max(0.0);
+    let right = coords.iter().map(|c| c.0).max().unwrap_or_default() as f64 + MARGIN;
+    let bottom = coords.iter().map(|c| c.1).max().unwrap_or_default() as f64 + MARGIN;
+    let inside = |(x, y): (f64, f64)| x >= left && x <= right && y >= top && y <= bottom;
+
+    for _ in 0..settings.num_of_rivers * ATTEMPTS_PER_RIVER {
+        if terrain.rivers.len() >= settings.num_of_rivers as usize {
+            break;
+        }
+
+        let source = (rng.gen_range(left..=right), rng.gen_range(top..=bottom));
+        let elevation = terrain.elevation(source.0, source.1);
+        if elevation < SOURCE_ELEVATION.0
+            || elevation > SOURCE_ELEVATION.1
+            || terrain.is_water(settings, source.0, source.1)
+        {
+            continue;
+        }
+
+        let mut points = vec![source];
+        let mut lake = None;
+
+        for _ in 0..MAX_STEPS {
+            let (x, y) = points[points.len() - 1];
+
+            let joins = terrain
+                .rivers
+                .iter()
+                .flat_map(|river| river.points.iter())
+                .find(|point| {
+                    let (dx, dy) = (point.0 as f64 - x, point.1 as f64 - y);
+                    (dx * dx + dy * dy).sqrt() < STEP
+                });
+            if let Some(&(jx, jy)) = joins {
+                points.push((jx as f64, jy as f64));
+                break;
+            }
+            if terrain.is_water(settings, x, y) {
+                break;
+            }
+
+            let next = (0..8)
+                .map(|i| {
+                    let angle = i as f64 * std::f64::consts::FRAC_PI_4;
+                    (x + angle.cos() * STEP, y + angle.sin() * STEP)
+                })
+                .min_by(|a, b| {
+                    terrain
+                        .elevation(a.0, a.1)
+                        .total_cmp(&terrain.elevation(b.0, b.1))
+                })
+                .expect("there are always eight directions");
+
+            if terrain.elevation(next.0, next.1) >= terrain.elevation(x, y) {
+                lake = Some(Lake {
+                    centre: (x.round() as u32, y.round() as u32),
+                    radius: LAKE_RADIUS,
+                });
+                break;
+            }
+
+            // Rivers that run off the map end at its edge
+            if !inside(next) {
+                break;
+            }
+            points.push(next);
+        }
+
+        if points.len() < 3 {
+            continue;
+        }
+
+        terrain.rivers.push(River {
+            points: points
+                .iter()
+                .map(|&(x, y)| (x.max(0.0).round() as u32, y.max(0.0).round() as u32))
+                .collect(),
+        });
+        terrain.lakes.extend(lake);
+    }
+}
+
+// Struct for the waters a town can reach by boat, the body of water in the WaterMap and the river with the
+// index of its nearest point
+#[derive(Debug, Clone, Default)]
+struct Harbour {
+    water_body: Option<usize>,
+    river: Option<(usize, usize)>,
+}
+
+impl Harbour {
+    fn is_port(&self) -> bool {
+        self.water_body.is_some() || self.river.is_some()
+    }
+}
+
+// Struct for a grid over the world where each water cell is labelled with the body of water it's part of,
+// towns next to the same body of water can reach each other by boat
+struct WaterMap {
+    left: f64,
+    top: f64,
+    columns: usize,
+    rows: usize,
+    labels: Vec<Option<usize>>,
+    bodies: usize,
+}
+
+impl WaterMap {
+    const CELL: f64 = 2.0;
+
+    fn new(settings: &AppConfig, terrain: &Terrain, coords: &[(u32, u32)]) -> Self {
+        const MARGIN: f64 = 30.0;
+
+        let left = coords.iter().map(|c| c.0).min().unwrap_or_default() as f64 - MARGIN;
+        let top = coords.iter().map(|c| c.1).min().unwrap_or_default() as f64 - MARGIN;
+        let right = coords.iter().map(|c| c.0).max().unwrap_or_default() as f64 + MARGIN;
+        let bottom = coords.iter().map(|c| c.1).max().unwrap_or_default() as f64 + MARGIN;
+        let columns = ((right - left) / Self::CELL).ceil() as usize + 1;
+        let rows = ((bottom - top) / Self::CELL).ceil() as usize + 1;
+
+        let centre = |i: usize| {
+            (
+                left + (i % columns) as f64 * Self::CELL,
+                top + (i / columns) as f64 * Self::CELL,
+            )
+        };
+        let water: Vec<bool> = (0..columns * rows)
+            .map(|i| {
+                let (x, y) = centre(i);
+                terrain.is_water(settings, x, y)
+            })
+            .collect();
+
+        // Flood fill each body of water
+        let mut labels = vec![None; columns * rows];
+        let mut bodies = 0;
+        for start in 0..columns * rows {
+            if !water[start] || labels[start].is_some() {
+                continue;
+            }
+
+            let label = bodies;
+            let mut stack = vec![start];
+            labels[start] = Some(label);
+
+            while let Some(i) = stack.pop() {
+                let (column, row) = (i % columns, i / columns);
+                let neighbours = [
+                    (column > 0).then(|| i - 1),
+                    (column + 1 < columns).then(|| i + 1),
+                    (row > 0).then(|| i - columns),
+                    (row + 1 < rows).then(|| i + columns),
+                ];
+                for neighbour in neighbours.into_iter().flatten() {
+                    if water[neighbour] && labels[neighbour].is_none() {
+                        labels[neighbour] = Some(label);
+                        stack.push(neighbour);
+                    }
+                }
+            }
+
+            bodies += 1;
+        }
+
+        Self {
+            left,
+            top,
+            columns,
+            rows,
+            labels,
+            bodies,
+        }
+    }
+
+    // Find the nearest body of water within a distance of a point
+    fn nearest_water(&self, (x, y): (u32, u32), distance: f64) -> Option<usize> {
+        let reach = (distance / Self::CELL).ceil() as i64;
+        let column = ((x as f64 - self.left) / Self::CELL).round() as i64;
+        let row = ((y as f64 - self.top) / Self::CELL).round() as i64;
+
+        (-reach..=reach)
+            .flat_map(|dy| (-reach..=reach).map(move |dx| (dx, dy)))
+            .filter(|&(dx, dy)| ((dx * dx + dy * dy) as f64).sqrt() * Self::CELL <= distance)
+            .filter_map(|(dx, dy)| {
+                let (c, r) = (column + dx, row + dy);
+                if c < 0 || r < 0 || c >= self.columns as i64 || r >= self.rows as i64 {
+                    return None;
+                }
+                self.labels[r as usize * self.columns + c as usize]
+                    .map(|label| (dx * dx + dy * dy, label))
+            })
+            .min_by_key(|&(distance, _)| distance)
+            .map(|(_, label)| label)
+    }
+}
+
+// Find the waters each town can reach, towns within the port distance of a sea, lake or river are ports
+fn find_harbours(
+    settings: &AppConfig,
+    terrain: &Terrain,
+    water_map: &WaterMap,
+    coords: &[(u32, u32)],
+) -> Vec<Harbour> {
+    let distance = settings.port_distance as f64;
+
+    coords
+        .iter()
+        .map(|&(x, y)| {
+            let river = terrain
+                .rivers
+                .iter()
+                .enumerate()
+                .flat_map(|(r, river)| {
+                    river.points.iter().enumerate().map(move |(p, point)| {
+                        let (dx, dy) = (point.0 as f64 - x as f64, point.1 as f64 - y as f64);
+                        ((dx * dx + dy * dy).sqrt(), (r, p))
+                    })
+                })
+                .filter(|(d, _)| *d <= distance)
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, river)| river);
+
+            Harbour {
+                water_body: water_map.nearest_water((x, y), distance),
+                river,
+            }
+        })
+        .collect()
+}
+
+// Add routes by boat between ports. Ports on the same sea or lake are linked by sea to their nearest
+// neighbours, and ports on a river are linked to the next port along it
+fn add_water_routes<N>(
+    settings: &AppConfig,
+    graph: &mut Graph<N, JourneyInfo>,
+    coords: &[(u32, u32)],
+    terrain: &Terrain,
+    water_map: &WaterMap,
+    harbours: &[Harbour],
+) {
+    const NEIGHBOURS: usize = 2;
+
+    let nodes: Vec<NodeIndex> = graph.node_indices().collect();
+
+    let straight_distance = |a: usize, b: usize| {
+        let (dx, dy) = (
+            coords[a].0 as f64 - coords[b].0 as f64,
+            coords[a].1 as f64 - coords[b].1 as f64,
+        );
+        (dx * dx + dy * dy).sqrt()
+    };
+
+    let mut routes: Vec<(usize, usize, u32, RouteKind)> = Vec::new();
+    let mut linked: HashSet<(usize, usize)> = HashSet::new();
+
+    for body in 0..water_map.bodies {
+        let ports: Vec<usize> = (0..harbours.len())
+            .filter(|&i| harbours[i].water_body == Some(body))
+            .collect();
+
+        for &port in &ports {
+            let nearest = ports
+                .iter()
+                .filter(|&&other| other != port)
+                .sorted_by(|&&a, &&b| {
+                    straight_distance(port, a).total_cmp(&straight_distance(port, b))
+                })
+                .take(NEIGHBOURS);
+
+            for &other in nearest {
+                if linked.insert((port.min(other), port.max(other))) {
+                    let distance = straight_distance(port, other).round().max(1.0) as u32;
+                    routes.push((port, other, distance, RouteKind::Sea));
+                }
+            }
+        }
+    }
+
+    for (r, river) in terrain.rivers.iter().enumerate() {
+        let ports: Vec<(usize, usize)> = harbours
+            .iter()
+            .enumerate()
+            .filter_map(|(i, harbour)| match harbour.river {
+                Some((river, point)) if river == r => Some((point, i)),
+                _ => None,
+            })
+            .sorted()
+            .collect();
+
+        for pair in ports.windows(2) {
+            let ((start, from), (end, to)) = (pair[0], pair[1]);
+            if !linked.insert((from.min(to), from.max(to))) {
+                continue;
+            }
+
+            let length: f64 = river.points[start..=end]
+                .windows(2)
+                .map(|segment| {
+                    let (dx, dy) = (
+                        segment[0].0 as f64 - segment[1].0 as f64,
+                        segment[0].1 as f64 - segment[1].1 as f64,
+                    );
+                    (dx * dx + dy * dy).sqrt()
+                })
+                .sum();
+            routes.push((from, to, length.round().max(1.0) as u32, RouteKind::River));
+        }
+    }
+
+    for (from, to, distance, route) in routes {
+        let rate = match route {
+            RouteKind::Road => settings.cost,
+            RouteKind::River => settings.river_cost,
+            RouteKind::Sea => settings.sea_cost,
+            RouteKind::Trail => settings.trail_cost,
+        };
+        graph.add_edge(
+            nodes[from],
+            nodes[to],
+            JourneyInfo::new(distance, rate * distance, route),
+        );
+    }
+}
+
+// Give every route a road type, unless it already has one from an imported DOT file, then work out the
+// journey's time and cost for each travel mode that can use it
+fn classify_routes(settings: &AppConfig, graph: &mut Graph<Town, JourneyInfo>, terrain: &Terrain) {
+    for edge in graph.edge_indices().collect::<Vec<_>>() {
+        let (source, target) = graph
+            .edge_endpoints(edge)
+            .expect("edge indices come from the graph");
+        let (from, to) = (&graph[source], &graph[target]);
+
+        // Highest point along the route
+        let (dx, dy) = (
+            to.coords.0 as f64 - from.coords.0 as f64,
+            to.coords.1 as f64 - from.coords.1 as f64,
+        );
+        let steps = (dx * dx + dy * dy).sqrt().ceil().max(1.0) as usize;
+        let highest = (0..=steps)
+            .map(|step| {
+                let t = step as f64 / steps as f64;
+                terrain.elevation(from.coords.0 as f64 + dx * t, from.coords.1 as f64 + dy * t)
+            })
+            .fold(0.0, f64::max);
+        let smallest_town = from.number_of_buildings.min(to.number_of_buildings);
+
+        let journey_info = &graph[edge];
+        let road_type = settings
+            .road_types
+            .iter()
+            .find(|road_type| road_type.name.eq_ignore_ascii_case(&journey_info.road_type))
+            .or_else(|| {
+                settings.road_types.iter().find(|road_type| {
+                    road_type.route == journey_info.route
+                        && road_type.min_elevation.is_none_or(|min| highest >= min)
+                        && road_type
+                            .min_buildings
+                            .is_none_or(|min| smallest_town >= min)
+                })
+            });
+
+        let journey_info = &mut graph[edge];
+        let Some(road_type) = road_type else {
+            journey_info.travel.clear();
+            continue;
+        };
+
+        journey_info.road_type = road_type.name.clone();
+        journey_info.danger = road_type.danger;
+        journey_info.toll = road_type.toll;
+        journey_info.travel = settings
+            .travel_modes
+            .iter()
+            .filter(|mode| mode.can_use(&road_type.name))
+            .map(|mode| {
+                let speed = road_type
+                    .fixed_speed
+                    .unwrap_or(mode.speed * road_type.speed)
+                    .max(0.1);
+                TravelInfo {
+                    mode: mode.name.clone(),
+                    hours: (journey_info.distance as f64 / speed * 10.0).round() / 10.0,
+                    cost: (mode.cost * journey_info.distance as f64).round() as u32
+                        + road_type.toll,
+                }
+            })
+            .collect();
+    }
+}
+
+// Add stops along the roads: crossroads where two roads cross, bridges and fords where a road crosses a
+// river, and waystations spaced along long roads. Each road with stops is split into a road between each
+// pair of neighbouring places, its distance and cost shared between them
+fn add_road_stops(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+    lists: &NameLists,
+    grammar: &Grammar,
+    graph: &mut Graph<Town, JourneyInfo>,
+    terrain: &Terrain,
+) {
+    // Stops too close to either end of a road are left out
+    const END_GAP: f64 = 0.05;
+
+    let stop_config = |kind: TownKind| settings.road_stops.iter().find(|stop| stop.kind == kind);
+    if settings.road_stops.is_empty() {
+        return;
+    }
+
+    print!("Generating road stops... ");
+
+    let point = |coords: (u32, u32)| (coords.0 as f64, coords.1 as f64);
+    let roads: Vec<(NodeIndex, NodeIndex, JourneyInfo)> = graph
+        .edge_references()
+        .filter(|edge| edge.weight().route == RouteKind::Road)
+        .map(|edge| (edge.source(), edge.target(), edge.weight().clone()))
+        .collect();
+    let ends = |road: &(NodeIndex, NodeIndex, JourneyInfo)| {
+        (point(graph[road.0].coords), point(graph[road.1].coords))
+    };
+    let inside = |t: f64| t > END_GAP && t < 1.0 - END_GAP;
+
+    // Where along each road its stops are, and the kind and position of each stop
+    let mut splits: Vec<Vec<(f64, usize)>> = vec![Vec::new(); roads.len()];
+    let mut stops: Vec<(TownKind, (f64, f64))> = Vec::new();
+
+    if stop_config(TownKind::Crossroads).is_some() {
+        for (i, j) in (0..roads.len()).tuple_combinations() {
+            let shares_town = [roads[i].0, roads[i].1]
+                .iter()
+                .any(|town| *town == roads[j].0 || *town == roads[j].1);
+            if shares_town {
+                continue;
+            }
+
+            let ((a, b), (c, d)) = (ends(&roads[i]), ends(&roads[j]));
+            if let Some((t, u)) = segment_intersection(a, b, c, d) {
+                if inside(t) && inside(u) {
+                    splits[i].push((t, stops.len()));
+                    splits[j].push((u, stops.len()));
+                    stops.push((
+                        TownKind::Crossroads,
+                        (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t),
+                    ));
+                }
+            }
+        }
+    }
+
+    // Rivers are shallow enough to ford in their upper half, and bridged further down
+    for (i, road) in roads.iter().enumerate() {
+        let (a, b) = ends(road);
+        for river in &terrain.rivers {
+            let crossing = river
+                .points
+                .windows(2)
+                .enumerate()
+                .find_map(|(k, segment)| {
+                    segment_intersection(a, b, point(segment[0]), point(segment[1]))
+                        .filter(|&(t, _)| inside(t))
+                        .map(|(t, _)| (k, t))
+                });
+
+            if let Some((k, t)) = crossing {
+                let kind = if k * 2 < river.points.len() {
+                    TownKind::Ford
+                } else {
+                    TownKind::Bridge
+                };
+                if stop_config(kind).is_some() {
+                    splits[i].push((t, stops.len()));
+                    stops.push((kind, (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)));
+                }
+            }
+        }
+    }
+
+    if stop_config(TownKind::Waystation).is_some() && settings.waystation_distance > 0 {
+        for (i, road) in roads.iter().enumerate() {
+            let (a, b) = ends(road);
+            let mut points: Vec<f64> = splits[i].iter().map(|&(t, _)| t).collect();
+            points.extend([0.0, 1.0]);
+            points.sort_by(f64::total_cmp);
+
+            for gap in points.windows(2) {
+                let length = (gap[1] - gap[0]) * road.2.distance as f64;
+                let count = (length / settings.waystation_distance as f64).ceil() as usize;
+                for k in 1..count {
+                    let t = gap[0] + (gap[1] - gap[0]) * k as f64 / count as f64;
+                    splits[i].push((t, stops.len()));
+                    stops.push((
+                        TownKind::Waystation,
+                        (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t),
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut used_names: HashSet<String> =
+        graph.node_weights().map(|town| town.name.clone()).collect();
+    let mut stop_nodes = Vec::new();
+    for (kind, (x, y)) in stops {
+        let building_types = place_building_types(settings, kind).unwrap_or_default();
+        let coords = (x.round() as u32, y.round() as u32);
+        let biome = terrain.biome(settings, x, y);
+        let town = generate_place(
+            settings,
+            rng,
+            id_tracker,
+            lists,
+            grammar,
+            kind,
+            &building_types,
+            coords,
+            biome,
+            &mut used_names,
+        );
+        stop_nodes.push(graph.add_node(town));
+    }
+
+    graph.retain_edges(|graph, edge| graph[edge].route != RouteKind::Road);
+    for ((from, to, journey_info), mut split) in roads.into_iter().zip(splits) {
+        split.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let places: Vec<(f64, NodeIndex)> = std::iter::once((0.0, from))
+            .chain(split.iter().map(|&(t, stop)| (t, stop_nodes[stop])))
+            .chain(std::iter::once((1.0, to)))
+            .collect();
+
+        for pair in places.windows(2) {
+            let ((start, a), (end, b)) = (pair[0], pair[1]);
+            let distance = ((end * journey_info.distance as f64).round()
+                - (start * journey_info.distance as f64).round())
+            .max(1.0) as u32;
+
+            let mut part = journey_info.clone();
+            part.distance = distance;
+            part.cost = settings.cost * distance;
+            graph.add_edge(a, b, part);
+        }
+    }
+
+    println!("done!");
+}
+
+// Get the building types of a stop along a road or a point of interest, None for towns or kinds that aren't
+// in the settings. A point of interest has a single building of its own kind
+fn place_building_types(settings: &AppConfig, kind: TownKind) -> Option<Vec<BuildingTypeConfig>> {
+    if kind.is_point_of_interest() {
+        return settings
+            .points_of_interest
+            .iter()
+            .find(|poi| poi.kind == kind)
+            .map(|poi| vec![poi.building_type()]);
+    }
+
+    let stop = settings
+        .road_stops
+        .iter()
+        .find(|stop| kind != TownKind::Town && stop.kind == kind)?;
+    Some(
+        settings
+            .building_types
+            .iter()
+            .flat_map(|type_config| {
+                std::iter::repeat_n(type_config, stop.building_count(&type_config.name) as usize)
+            })
+            .cloned()
+            .collect(),
+    )
+}
+
+// Get the DOT node style of a stop along a road or a point of interest
+fn place_dot_style(settings: &AppConfig, kind: TownKind) -> &str {
+    let stop_style = settings
+        .road_stops
+        .iter()
+        .find(|stop| kind != TownKind::Town && stop.kind == kind)
+        .map(|stop| stop.dot_style.as_str());
+    let poi_style = settings
+        .points_of_interest
+        .iter()
+        .find(|poi| poi.kind == kind)
+        .map(|poi| poi.dot_style.as_str());
+
+    stop_style.or(poi_style).unwrap_or_default()
+}
+
+// Generate a stop along a road or a point of interest with the given buildings, buildings of the place's own
+// kind are named after it. Its name is kept different from the used names so the DOT file can be imported
+// again
+#[allow(clippy::too_many_arguments)]
+fn generate_place(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+    lists: &NameLists,
+    grammar: &Grammar,
+    kind: TownKind,
+    building_types: &[BuildingTypeConfig],
+    coords: (u32, u32),
+    biome: &BiomeConfig,
+    used_names: &mut HashSet<String>,
+) -> Town {
+    let town_id = id_tracker.get_new_id(settings);
+    let culture = choose_town_culture(settings, rng);
+
+    let symbols = HashMap::from([(
+        "root",
+        GrammarSymbol::Names(&lists.town_names(culture).roots),
+    )]);
+    let tags: Vec<String> = culture
+        .map(|culture| culture.name.to_lowercase())
+        .into_iter()
+        .chain([biome.name.to_lowercase()])
+        .collect();
+
+    let mut name = grammar.generate(rng, &kind.key(), &symbols, &tags);
+    for _ in 0..10 {
+        if !used_names.contains(&name) {
+            break;
+        }
+        name = grammar.generate(rng, &kind.key(), &symbols, &tags);
+    }
+    let name = unique_name(name, used_names);
+
+    let mut buildings = generate_buildings_of_types(
+        settings,
+        rng,
+        id_tracker,
+        lists,
+        grammar,
+        &town_id,
+        culture,
+        building_types.iter().collect(),
+        0,
+    );
+    for building in &mut buildings {
+        if building.building_type.0 == kind.name() {
+            building.name = name.clone();
+            if let Some(dungeon) = &mut building.dungeon {
+                dungeon.name = name.clone();
+            }
+        }
+    }
+    let layout = generate_layout(rng, grammar, &mut buildings);
+
+    Town {
+        id: town_id,
+        name,
+        culture: culture.map(|culture| culture.name.clone()),
+        size: TownSize(kind.name().into()),
+        population: buildings
+            .iter()
+            .flat_map(|building| building.rooms.iter())
+            .map(|room| room.npcs.len() as u32)
+            .sum(),
+        coords,
+        biome: Biome(biome.name.clone()),
+        port: false,
+        kind,
+        capital: false,
+        metrics: TownMetrics::default(),
+        number_of_buildings: buildings.len() as u32,
+        buildings,
+        layout,
+    }
+}
+
+// Add points of interest on land away from the roads, each connected by trails to the nearest town and to
+// the next nearest if it's within the trail distance
+fn add_points_of_interest(
+    settings: &AppConfig,
+    rng: &mut StdRng,
+    id_tracker: &mut IdTracker,
+    lists: &NameLists,
+    grammar: &Grammar,
+    graph: &mut Graph<Town, JourneyInfo>,
+    terrain: &Terrain,
+) {
+    // How far points of interest are kept from roads and other places
+    const CLEARANCE: f64 = 8.0;
+    const TRIES: usize = 50;
+
+    let towns: Vec<NodeIndex> = graph
+        .node_indices()
+        .filter(|&node| graph[node].kind == TownKind::Town)
+        .collect();
+    if towns.is_empty() || settings.points_of_interest.is_empty() {
+        return;
+    }
+
+    print!("Generating points of interest... ");
+
+    let point = |coords: (u32, u32)| (coords.0 as f64, coords.1 as f64);
+    let mut places: Vec<(f64, f64)> = graph
+        .node_weights()
+        .map(|town| point(town.coords))
+        .collect();
+    let mut routes: Vec<((f64, f64), (f64, f64))> = graph
+        .edge_references()
+        .map(|edge| {
+            (
+                point(graph[edge.source()].coords),
+                point(graph[edge.target()].coords),
+            )
+        })
+        .collect();
+
+    let (min_x, max_x) = places
+        .iter()
+        .map(|place| place.0)
+        .fold((f64::MAX, f64::MIN), |(min, max), x| {
+            (min.min(x), max.max(x))
+        });
+    let (min_y, max_y) = places
+        .iter()
+        .map(|place| place.1)
+        .fold((f64::MAX, f64::MIN), |(min, max), y| {
+            (min.min(y), max.max(y))
+        });
+
+    let mut used_names: HashSet<String> =
+        graph.node_weights().map(|town| town.name.clone()).collect();
+
+    for _ in 0..settings.num_of_points_of_interest {
+        for _ in 0..TRIES {
+            let (x, y) = (rng.gen_range(min_x..=max_x), rng.gen_range(min_y..=max_y));
+
+            let clear = !terrain.is_water(settings, x, y)
+                && places
+                    .iter()
+                    .all(|place| (place.0 - x).hypot(place.1 - y) >= CLEARANCE)
+                && routes
+                    .iter()
+                    .all(|&(a, b)| segment_distance((x, y), a, b) >= CLEARANCE);
+            if !clear {
+                continue;
+            }
+
+            let biome = terrain.biome(settings, x, y);
+            let candidates: Vec<&PointOfInterestConfig> = settings
+                .points_of_interest
+                .iter()
+                .filter(|poi| poi.allows(biome))
+                .collect();
+            let Ok(poi) = candidates.choose_weighted(rng, |poi| poi.weight) else {
+                continue;
+            };
+
+            let coords = (x.round() as u32, y.round() as u32);
+            let town = generate_place(
+                settings,
+                rng,
+                id_tracker,
+                lists,
+                grammar,
+                poi.kind,
+                &[poi.building_type()],
+                coords,
+                biome,
+                &mut used_names,
+            );
+            let node = graph.add_node(town);
+            places.push((x, y));
+
+            let nearest = towns
+                .iter()
+                .map(|&town| {
+                    let (tx, ty) = point(graph[town].coords);
+                    (town, (tx - x).hypot(ty - y))
+                })
+                .sorted_by(|a, b| a.1.total_cmp(&b.1))
+                .take(2)
+                .enumerate()
+                .filter(|&(i, (_, distance))| i == 0 || distance <= settings.trail_distance as f64)
+                .map(|(_, nearest)| nearest)
+                .collect::<Vec<_>>();
+
+            for (town, distance) in nearest {
+                let distance = distance.round().max(1.0) as u32;
+                graph.add_edge(
+                    node,
+                    town,
+                    JourneyInfo::new(distance, settings.trail_cost * distance, RouteKind::Trail),
+                );
+                routes.push(((x, y), point(graph[town].coords)));
+            }
+            break;
+        }
+    }
+
+    println!("done!");
+}
+
+// Function to get the distance from a point to a line segment
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (a.0 + dx * t - p.0).hypot(a.1 + dy * t - p.1)
+}
+
+// Function to find where two line segments cross, returns how far along each segment the crossing is
+fn segment_intersection(
+    a: (f64, f64),
+    b: (f64, f64),
+    c: (f64, f64),
+    d: (f64, f64),
+) -> Option<(f64, f64)> {
+    let (r, s) = ((b.0 - a.0, b.1 - a.1), (d.0 - c.0, d.1 - c.1));
+    let denominator = r.0 * s.1 - r.1 * s.0;
+    if denominator.abs() < f64::EPSILON {
+        return None;
+    }
+
+    let (qx, qy) = (c.0 - a.0, c.1 - a.1);
+    let t = (qx * s.1 - qy * s.0) / denominator;
+    let u = (qx * r.1 - qy * r.0) / denominator;
+
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some((t, u))
+}
+
+// Type alias for the coordinates and biome of each town placed on the terrain
+type TownPlacements<'a> = Vec<((u32, u32), &'a BiomeConfig)>;
+
+// Function to get a random number between 0 and 1 for a point on a grid
+fn lattice_value(seed: u64, x: i64, y: i64) -> f64 {
+    let mut hash = seed
+        ^ (x as u64).wrapping_mul(0x9e3779b97f4a7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2ae3d27d4eb4f);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^= hash >> 33;
+
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Function to get smooth noise between 0 and 1 by interpolating between random values on a grid
+fn value_noise(seed: u64, x: f64, y: f64) -> f64 {
+    let (left, top) = (x.floor(), y.floor());
+    let (ix, iy) = (left as i64, top as i64);
+    let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+    let (sx, sy) = (smooth(x - left), smooth(y - top));
+
+    let top_edge = lattice_value(seed, ix, iy)
+        + (lattice_value(seed, ix + 1, iy) - lattice_value(seed, ix, iy)) * sx;
+    let bottom_edge = lattice_value(seed, ix, iy + 1)
+        + (lattice_value(seed, ix + 1, iy + 1) - lattice_value(seed, ix, iy + 1)) * sx;
+
+    top_edge + (bottom_edge - top_edge) * sy
+}
+
+// Function to get noise between 0 and 1 with detail at several scales, stretched so the whole range is used
+fn fractal_noise(seed: u64, x: f64, y: f64) -> f64 {
+    const OCTAVES: u32 = 4;
+
+    let (mut total, mut amplitude, mut frequency, mut total_amplitude) = (0.0, 1.0, 1.0, 0.0);
+    for octave in 0..OCTAVES {
+        total += value_noise(
+            seed.wrapping_add(octave as u64),
+            x * frequency,
+            y * frequency,
+        ) * amplitude;
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    ((total / total_amplitude - 0.5) * 1.8 + 0.5).clamp(0.0, 1.0)
+}
+
+// Function to list the edges of a graph as roads between town IDs
+fn roads_from_graph(graph: &Graph<Town, JourneyInfo>) -> Vec<Road> {
+    graph
+        .edge_references()
+        .map(|edge| Road {
+            from: graph[edge.source()].id,
+            to: graph[edge.target()].id,
+            journey_info: edge.weight().clone(),
+        })
+        .collect()
+}
+
+// Rebuild the graph of a world from its towns and roads
+fn graph_from_world(world: &World) -> Graph<Town, JourneyInfo> {
+    let mut graph = Graph::<Town, JourneyInfo>::new();
+
+    let mut towns: Vec<&Town> = world.towns.values().collect();
+    towns.sort_by_key(|town| town.id);
+
+    let nodes: HashMap<u32, NodeIndex> = towns
+        .into_iter()
+        .map(|town| (town.id, graph.add_node(town.clone())))
+        .collect();
+
+    for road in &world.roads {
+        if let (Some(&from), Some(&to)) = (nodes.get(&road.from), nodes.get(&road.to)) {
+            graph.add_edge(from, to, road.journey_info.clone());
+        }
+    }
+
+    graph
+}
+
+// Enum for what the route planner minimises
+#[derive(Debug, Clone, Copy, PartialEq, EnumIter)]
+enum RouteMetric {
+    Distance,
+    Cost,
+    Time,
+}
+
+// Struct for the route planner's options. Without a travel mode each leg uses the cheapest mode that can
+// travel it when planning by cost and the fastest otherwise, at that mode's time and cost
+#[derive(Debug, Clone)]
+struct RouteOptions {
+    metric: RouteMetric,
+    travel_mode: Option<String>,
+    waypoints: Vec<NodeIndex>,
+    avoid_towns: Vec<NodeIndex>,
+    avoid_road_types: Vec<String>,
+}
+
+impl RouteOptions {
+    fn new(metric: RouteMetric) -> Self {
+        Self {
+            metric,
+            travel_mode: None,
+            waypoints: Vec::new(),
+            avoid_towns: Vec::new(),
+            avoid_road_types: Vec::new(),
+        }
+    }
+}
+
+// Struct for one leg of a planned route, a single road between two towns
+#[derive(Debug, Clone)]
+struct RouteLeg {
+    from: NodeIndex,
+    to: NodeIndex,
+    journey_info: JourneyInfo,
+    mode: Option<String>,
+    hours: Option<f64>,
+    cost: u32,
+}
+
+// Struct for a planned route, with the totals of its legs
+#[derive(Debug, Clone)]
+struct Itinerary {
+    legs: Vec<RouteLeg>,
+    distance: u32,
+    cost: u32,
+    hours: Option<f64>,
+}
+
+// Function to get the travel mode, time and cost of a road for the route planner, returns None if the road
+// can't be used
+fn route_leg_info(
+    journey_info: &JourneyInfo,
+    options: &RouteOptions,
+) -> Option<(Option<String>, Option<f64>, u32)> {
+    if options
+        .avoid_road_types
+        .iter()
+        .any(|road_type| road_type.eq_ignore_ascii_case(&journey_info.road_type))
+    {
+        return None;
+    }
+
+    match &options.travel_mode {
+        Some(mode) => journey_info
+            .travel
+            .iter()
+            .find(|travel| travel.mode.eq_ignore_ascii_case(mode))
+            .map(|travel| (Some(travel.mode.clone()), Some(travel.hours), travel.cost)),
+        None => {
+            let best = journey_info
+                .travel
+                .iter()
+                .min_by(|a, b| match options.metric {
+                    RouteMetric::Cost => a.cost.cmp(&b.cost).then(a.hours.total_cmp(&b.hours)),
+                    _ => a.hours.total_cmp(&b.hours).then(a.cost.cmp(&b.cost)),
+                });
+            // Routes no travel mode can use keep their own cost
+            Some((
+                best.map(|travel| travel.mode.clone()),
+                best.map(|travel| travel.hours),
+                best.map_or(journey_info.cost, |travel| travel.cost),
+            ))
+        }
+    }
+}
+
+// Function to get the weight of a road for the route planner, roads that can't be used have infinite weight
+fn route_weight(journey_info: &JourneyInfo, options: &RouteOptions) -> f64 {
+    match route_leg_info(journey_info, options) {
+        Some((_, hours, cost)) => match options.metric {
+            RouteMetric::Distance => journey_info.distance as f64,
+            RouteMetric::Cost => cost as f64,
+            RouteMetric::Time => hours.unwrap_or(f64::INFINITY),
+        },
+        None => f64::INFINITY,
+    }
+}
+
+// Plan the shortest route between two towns by distance, cost or time, visiting the waypoints in order and
+// avoiding the given towns and road types. Returns None if there's no such route
+fn plan_route(
+    graph: &Graph<Town, JourneyInfo>,
+    from: NodeIndex,
+    to: NodeIndex,
+    options: &RouteOptions,
+) -> Option<Itinerary> {
+    let stops: Vec<NodeIndex> = std::iter::once(from)
+        .chain(options.waypoints.iter().copied())
+        .chain(std::iter::once(to))
+        .collect();
+    let avoid: HashSet<NodeIndex> = options
+        .avoid_towns
+        .iter()
+        .filter(|town| !stops.contains(town))
+        .copied()
+        .collect();
+
+    // Roads can be travelled in either direction
+    let roads = graph
+        .map(|_, town| town, |_, journey_info| journey_info)
+        .into_edge_type::<Undirected>();
+
+    let mut legs = Vec::new();
+    for (&start, &goal) in stops.iter().tuple_windows() {
+        let (total, path) = astar(
+            &roads,
+            start,
+            |node| node == goal,
+            |edge| {
+                if avoid.contains(&edge.source()) || avoid.contains(&edge.target()) {
+                    f64::INFINITY
+                } else {
+                    route_weight(edge.weight(), options)
+                }
+            },
+            |_| 0.0,
+        )?;
+        if !total.is_finite() {
+            return None;
+        }
+
+        // Towns can be linked by more than one route, use the best one for each step
+        for (&a, &b) in path.iter().tuple_windows() {
+            let journey_info = roads
+                .edges_connecting(a, b)
+                .map(|edge| *edge.weight())
+                .min_by(|x, y| route_weight(x, options).total_cmp(&route_weight(y, options)))?;
+            let (mode, hours, cost) = route_leg_info(journey_info, options)?;
+
+            legs.push(RouteLeg {
+                from: a,
+                to: b,
+                journey_info: journey_info.clone(),
+                mode,
+                hours,
+                cost,
+            });
+        }
+    }
+
+    Some(Itinerary {
+        distance: legs.iter().map(|leg| leg.journey_info.distance).sum(),
+        cost: legs.iter().map(|leg| leg.cost).sum(),
+        hours: legs
+            .iter()
+            .try_fold(0.0, |total, leg| leg.hours.map(|hours| total + hours)),
+        legs,
+    })
+}
+
+// Render a planned route as an itinerary, leg by leg with the totals at the end
+fn render_itinerary(graph: &Graph<Town, JourneyInfo>, itinerary: &Itinerary) -> String {
+    let mut lines = Vec::new();
+
+    for (number, leg) in itinerary.legs.iter().enumerate() {
+        let road_type = match leg.journey_info.road_type.as_str() {
+            "" => format!("{:?}", leg.journey_info.route),
+            road_type => road_type.to_string(),
+        };
+        let time = match (&leg.mode, leg.hours) {
+            (Some(mode), Some(hours)) => format!(", {:.1} h by {}", hours, mode),
+            _ => String::new(),
+        };
+
+        lines.push(format!(
+            "{:>3}. {} -> {}: {} m along {}{}, {} gold",
+            number + 1,
+            graph[leg.from].name,
+            graph[leg.to].name,
+            leg.journey_info.distance,
+            road_type,
+            time,
+            leg.cost
+        ));
+    }
+
+    let time = match itinerary.hours {
+        Some(hours) => format!(", {:.1} h", hours),
+        None => String::new(),
+    };
+    lines.push(format!(
+        "Total: {} legs, {} m{}, {} gold",
+        itinerary.legs.len(),
+        itinerary.distance,
+        time,
+        itinerary.cost
+    ));
+
+    lines.join("\n")
+}
+
+// Struct for the shortest distance, cost and time between every pair of towns, rows and columns are in the
+// order of the town IDs in towns
+#[derive(Serialize, Debug)]
+struct TravelMatrix {
+    towns: Vec<u32>,
+    distance: PathMatrix,
+    cost: PathMatrix,
+    time: PathMatrix,
+}
+
+// Struct for the shortest paths between every pair of towns by one metric. The next hop is the ID of the
+// first town to travel to on the way, past any stops along the roads, None if there's no route
+#[derive(Serialize, Debug)]
+struct PathMatrix {
+    values: Vec<Vec<Option<f64>>>,
+    next_hop: Vec<Vec<Option<u32>>>,
+}
+
+// Generate the travel matrix of a graph, travel time uses the fastest travel mode on each road and cost
+// the cheapest.
+// Stops along the roads and points of interest aren't in the matrix, but routes pass through them
+fn generate_travel_matrix(graph: &Graph<Town, JourneyInfo>) -> TravelMatrix {
+    let towns: Vec<NodeIndex> = graph
+        .node_indices()
+        .filter(|&node| graph[node].kind == TownKind::Town)
+        .collect();
+
+    TravelMatrix {
+        towns: towns.iter().map(|&town| graph[town].id).collect(),
+        distance: generate_path_matrix(graph, &towns, RouteMetric::Distance),
+        cost: generate_path_matrix(graph, &towns, RouteMetric::Cost),
+        time: generate_path_matrix(graph, &towns, RouteMetric::Time),
+    }
+}
+
+// Find the shortest paths between every pair of the given towns by a metric (Floyd-Warshall), keeping the
+// next town on each path so it can be followed
+fn generate_path_matrix(
+    graph: &Graph<Town, JourneyInfo>,
+    towns: &[NodeIndex],
+    metric: RouteMetric,
+) -> PathMatrix {
+    let n = graph.node_count();
+    let options = RouteOptions::new(metric);
+
+    let mut values = vec![vec![f64::INFINITY; n]; n];
+    let mut next_hop: Vec<Vec<Option<usize>>> = vec![vec![None; n]; n];
+    for i in 0..n {
+        values[i][i] = 0.0;
+        next_hop[i][i] = Some(i);
+    }
+    for edge in graph.edge_references() {
+        let (a, b) = (edge.source().index(), edge.target().index());
+        let weight = route_weight(edge.weight(), &options);
+        if weight < values[a][b] {
+            values[a][b] = weight;
+            values[b][a] = weight;
+            next_hop[a][b] = Some(b);
+            next_hop[b][a] = Some(a);
+        }
+    }
+    for k in 0..n {
+        for i in 0..n {
+            for j in 0..n {
+                let through_k = values[i][k] + values[k][j];
+                if through_k < values[i][j] {
+                    values[i][j] = through_k;
+                    next_hop[i][j] = next_hop[i][k];
+                }
+            }
+        }
+    }
+
+    // Follow the path past any stops and points of interest to the next town on it
+    let next_town = |from: usize, to: usize| {
+        let mut hop = next_hop[from][to]?;
+        for _ in 0..n {
+            if hop == to || graph[NodeIndex::new(hop)].kind == TownKind::Town {
+                break;
+            }
+            hop = next_hop[hop][to]?;
+        }
+        Some(graph[NodeIndex::new(hop)].id)
+    };
+
+    PathMatrix {
+        values: towns
+            .iter()
+            .map(|from| {
+                towns
+                    .iter()
+                    .map(|to| {
+                        let value = values[from.index()][to.index()];
+                        value.is_finite().then_some((value * 10.0).round() / 10.0)
+                    })
+                    .collect()
+            })
+            .collect(),
+        next_hop: towns
+            .iter()
+            .map(|from| {
+                towns
+                    .iter()
+                    .map(|to| next_town(from.index(), to.index()))
+                    .collect()
+            })
+            .collect(),
+    }
+}
+
+// Save the travel matrix to a JSON file
+fn save_travel_matrix(
+    settings: &AppConfig,
+    matrix: &TravelMatrix,
+    filename: &str,
+) -> Result<String, std::io::Error> {
+    print!("Saving travel matrix to file: \"{}\"... ", filename);
+
+    let json = serde_json::to_string_pretty(matrix)?;
+
+    let filepath = format!("{}/{}", settings.output_dir, filename);
+    fs::create_dir_all(settings.output_dir.clone())?;
+    fs::write(filepath, json)?;
+
+    Ok("done!".into())
+}
+
+// Save the travel matrix to a CSV file, one row for every pair of different towns. Fields are left empty
+// where there's no route
+fn save_travel_matrix_csv(
+    settings: &AppConfig,
+    matrix: &TravelMatrix,
+    filename: &str,
+) -> Result<String, std::io::Error> {
+    print!("Saving travel matrix to file: \"{}\"... ", filename);
+
+    let field = |value: Option<String>| value.unwrap_or_default();
+
+    let mut csv =
+        String::from("from,to,distance,cost,hours,distance_next_hop,cost_next_hop,time_next_hop\n");
+    for (i, from) in matrix.towns.iter().enumerate() {
+        for (j, to) in matrix.towns.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+
+            let row = [
+                from.to_string(),
+                to.to_string(),
+                field(matrix.distance.values[i][j].map(|value| value.to_string())),
+                field(matrix.cost.values[i][j].map(|value| value.to_string())),
+                field(matrix.time.values[i][j].map(|value| value.to_string())),
+                field(matrix.distance.next_hop[i][j].map(|id| id.to_string())),
+                field(matrix.cost.next_hop[i][j].map(|id| id.to_string())),
+                field(matrix.time.next_hop[i][j].map(|id| id.to_string())),
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+    }
+
+    let filepath = format!("{}/{}", settings.output_dir, filename);
+    fs::create_dir_all(settings.output_dir.clone())?;
+    fs::write(filepath, csv)?;
+
+    Ok("done!".into())
+}
+
+// Save graph to a DOT file
+fn save_graph(
+    settings: &AppConfig,
+    graph: &Graph<Town, JourneyInfo>,
+    filename: &str,
+) -> Result<String, std::io::Error> {
+    print!("Saving graph to file: \"{}\"... ", filename);
+
+    let mut dot_output = String::from("graph Towns {\n");
+
+    for town in graph.node_weights() {
+        let size_style = settings
+            .town_sizes
+            .iter()
+            .find(|size| size.name == town.size.0)
+            .map(|size| size.dot_style.as_str())
+            .unwrap_or_default();
+        let capital_style = match town.capital {
+            true => settings.capital_dot_style.as_str(),
+            false => "",
+        };
+        let stop_style = place_dot_style(settings, town.kind);
+        let kind = match town.kind {
+            TownKind::Town => String::new(),
+            kind => format!("kind=\"{:?}\"", kind),
+        };
+        let style = [kind.as_str(), size_style, stop_style, capital_style]
+            .into_iter()
+            .filter(|style| !style.is_empty())
+            .join(", ");
+
+        if !style.is_empty() {
+            dot_output.push_str(&format!("    \"{}\" [{}];\n", town.name, style));
+        }
+    }
+
+    for edge in graph.edge_references() {
+        let source_town = &graph[edge.source()];
+        let target_town = &graph[edge.target()];
+        let journey_info = edge.weight();
+
+        let style = match journey_info.route {
+            RouteKind::Road => String::new(),
+            RouteKind::River => format!(", {}", settings.river_route_style),
+            RouteKind::Sea => format!(", {}", settings.sea_route_style),
+            RouteKind::Trail => format!(", {}", settings.trail_route_style),
+        };
+
+        dot_output.push_str(&format!(
+            "    \"{}\" -- \"{}\" [label=\"{}\", len={}{}];\n",
+            source_town.name,
+            target_town.name,
+            journey_info.dot_label(),
+            journey_info.distance / 10,
+            style
+        ));
+    }
+
+    dot_output.push_str("}\n");
+
+    let filepath = format!("{}/{}", settings.output_dir, filename);
+    fs::create_dir_all(settings.output_dir.clone())?;
+    fs::write(filepath, dot_output)?;
+
+    Ok("done!".into())
 }
 
-// Generate NPCs
-fn generate_npcs(
+// Save a dungeon's rooms and passages to a DOT file, rooms are labelled with their kind, depth and contents
+fn save_dungeon(
     settings: &AppConfig,
-    rng: &mut StdRng,
-    id_tracker: &mut IdTracker,
-    town_id: &u32,
-    building_id: &u32,
-    building_name: &str,
-    building_type: &BuildingType,
-) -> Vec<Npc> {
-    let mut npcs = Vec::new();
-
-    let names_male = load_list(settings, "names-male.txt");
-    let names_female = load_list(settings, "names-female.txt");
-    let names_unisex = load_list(settings, "names-unisex.txt");
-    let surnames = load_list(settings, "surnames.txt");
+    dungeon: &Dungeon,
+    rooms: &HashMap<u32, Room>,
+    filename: &str,
+) -> Result<String, std::io::Error> {
+    print!("Saving dungeon to file: \"{}\"... ", filename);
 
-    let number_of_npcs = match building_type {
-        BuildingType::Shop => 1,
-        BuildingType::Residence => 2,
-        _ => rng.gen_range(settings.min_npcs..settings.max_npcs),
-    };
+    let mut dot_output = format!("graph \"{}\" {{\n", dungeon.name);
 
-    for _ in 0..number_of_npcs {
-        let npc_id = id_tracker.get_new_id(settings);
+    for room in &dungeon.rooms {
+        let (npcs, containers) = rooms
+            .get(&room.room_id)
+            .map(|room| (room.npcs.len(), room.containers.len()))
+            .unwrap_or_default();
 
-        let sex = match rng.gen_range(0..NpcSex::COUNT) {
-            0 => NpcSex::Male,
-            1 => NpcSex::Female,
-            2 => NpcSex::Unisex,
-            _ => NpcSex::Unisex,
-        };
+        dot_output.push_str(&format!(
+            "    \"{}\" [label=\"{:?}\\ndepth {}\\n{} NPCs, {} containers\", shape={}];\n",
+            room.room_id,
+            room.kind,
+            room.depth,
+            npcs,
+            containers,
+            room.kind.dot_shape()
+        ));
+    }
 
-        let race = match rng.gen_range(0..NpcRace::COUNT) {
-            0 => NpcRace::Human,
-            1 => NpcRace::Elf,
-            _ => NpcRace::Human,
+    for passage in &dungeon.passages {
+        let style = match passage.locked {
+            true => format!(" [label=\"locked\", {}]", settings.locked_door_style),
+            false => String::new(),
         };
 
-        npcs.push(Npc {
-            id: npc_id,
-            name: generate_npc_name(
-                rng,
-                building_name,
-                building_type,
-                &sex,
-                &names_male,
-                &names_female,
-                &names_unisex,
-                &surnames,
-            ),
-            sex,
-            race,
-            town_id: *town_id,
-            building_id: *building_id,
-            room_id: None,
-        });
+        dot_output.push_str(&format!(
+            "    \"{}\" -- \"{}\"{};\n",
+            passage.from, passage.to, style
+        ));
     }
 
-    npcs
+    dot_output.push_str("}\n");
+
+    let filepath = format!("{}/{}", settings.output_dir, filename);
+    fs::create_dir_all(settings.output_dir.clone())?;
+    fs::write(filepath, dot_output)?;
+
+    Ok("done!".into())
 }
 
-// Generate an NPC name
-#[allow(clippy::too_many_arguments)]
-fn generate_npc_name(
-    rng: &mut StdRng,
-    building_name: &str,
-    building_type: &BuildingType,
-    npc_sex: &NpcSex,
-    names_male: &[String],
-    names_female: &[String],
-    names_unisex: &[String],
-    surnames: &[String],
-) -> String {
-    let firstname: String;
+// Save the world's road network, and optionally a map of each town, to an SVG file
+fn save_svg(
+    settings: &AppConfig,
+    graph: &Graph<Town, JourneyInfo>,
+    terrain: &Terrain,
+    filename: &str,
+) -> Result<String, std::io::Error> {
+    print!("Saving map to file: \"{}\"... ", filename);
 
-    match npc_sex {
-        NpcSex::Male => {
-            if let Some(name) = names_male.get(rng.gen_range(0..names_male.len())) {
-                firstname = name.to_string();
-            } else {
-                firstname = "NO DATA".into();
-            }
-        }
-        NpcSex::Female => {
-            if let Some(name) = names_female.get(rng.gen_range(0..names_female.len())) {
-                firstname = name.to_string();
+    const MARGIN: f64 = 60.0;
+    const LABEL_WIDTH: f64 = 140.0;
+    const INSET_SIZE: f64 = 280.0;
+    const INSET_GAP: f64 = 20.0;
+
+    let width = settings.svg_width.max(400) as f64;
+    let towns: Vec<&Town> = graph.node_weights().collect();
+
+    let min_x = towns
+        .iter()
+        .map(|town| town.coords.0)
+        .min()
+        .unwrap_or_default() as f64;
+    let min_y = towns
+        .iter()
+        .map(|town| town.coords.1)
+        .min()
+        .unwrap_or_default() as f64;
+    let max_x = towns
+        .iter()
+        .map(|town| town.coords.0)
+        .max()
+        .unwrap_or_default() as f64;
+    let max_y = towns
+        .iter()
+        .map(|town| town.coords.1)
+        .max()
+        .unwrap_or_default() as f64;
+
+    // Leave room on the right for the names of the easternmost towns
+    let scale = (width - MARGIN * 2.0 - LABEL_WIDTH) / (max_x - min_x).max(1.0);
+    let map_height = ((max_y - min_y) * scale + MARGIN * 2.0).ceil();
+    let position = |(x, y): (u32, u32)| {
+        (
+            (x as f64 - min_x) * scale + MARGIN,
+            (y as f64 - min_y) * scale + MARGIN,
+        )
+    };
+    let point = |town: &Town| position(town.coords);
+
+    let insets_per_row = ((width - INSET_GAP) / (INSET_SIZE + INSET_GAP))
+        .floor()
+        .max(1.0) as usize;
+    let inset_towns: Vec<&Town> = towns
+        .iter()
+        .filter(|town| !town.buildings.is_empty())
+        .copied()
+        .collect();
+    let inset_rows = if settings.svg_town_insets {
+        inset_towns.len().div_ceil(insets_per_row)
+    } else {
+        0
+    };
+    let height = (map_height + inset_rows as f64 * (INSET_SIZE + INSET_GAP)).ceil();
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\">\n",
+        width, height
+    );
+    svg.push_str(&format!(
+        "  <rect width=\"{}\" height=\"{}\" fill=\"#f4ecd8\"/>\n",
+        width, height
+    ));
+
+    // Only the rivers and lakes are drawn, the rest of the terrain is left to the PNG map
+    svg.push_str("  <g id=\"waters\" fill=\"#4a7ab0\" stroke=\"#4a7ab0\">\n");
+    for lake in &terrain.lakes {
+        let (x, y) = position(lake.centre);
+        svg.push_str(&format!(
+            "    <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{:.1}\"/>\n",
+            x,
+            y,
+            lake.radius as f64 * scale
+        ));
+    }
+    for river in &terrain.rivers {
+        let points = river
+            .points
+            .iter()
+            .map(|&point| {
+                let (x, y) = position(point);
+                format!("{:.1},{:.1}", x, y)
+            })
+            .join(" ");
+        svg.push_str(&format!(
+            "    <polyline points=\"{}\" fill=\"none\" stroke-width=\"3\"/>\n",
+            points
+        ));
+    }
+    svg.push_str("  </g>\n");
+
+    svg.push_str("  <g id=\"roads\" stroke=\"#8b6b4a\" stroke-width=\"2\">\n");
+    for edge in graph.edge_references() {
+        let (x1, y1) = point(&graph[edge.source()]);
+        let (x2, y2) = point(&graph[edge.target()]);
+        let style = match edge.weight().route {
+            RouteKind::Road => "",
+            RouteKind::River => " stroke=\"#2f5f8f\" stroke-dasharray=\"6 4\"",
+            RouteKind::Sea => " stroke=\"#1f3f6f\" stroke-dasharray=\"10 5\"",
+            RouteKind::Trail => " stroke-width=\"1.5\" stroke-dasharray=\"2 3\"",
+        };
+        svg.push_str(&format!(
+            "    <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"{}/>\n",
+            x1, y1, x2, y2, style
+        ));
+    }
+    svg.push_str("  </g>\n");
+
+    svg.push_str(
+        "  <g id=\"road-labels\" font-size=\"10\" fill=\"#5a4632\" text-anchor=\"middle\">\n",
+    );
+    for edge in graph.edge_references() {
+        let (x1, y1) = point(&graph[edge.source()]);
+        let (x2, y2) = point(&graph[edge.target()]);
+        svg.push_str(&format!(
+            "    <text x=\"{:.1}\" y=\"{:.1}\">{}</text>\n",
+            (x1 + x2) / 2.0,
+            (y1 + y2) / 2.0 - 3.0,
+            edge.weight().label()
+        ));
+    }
+    svg.push_str("  </g>\n");
+
+    // Town markers grow with the number of buildings
+    let most_buildings = towns
+        .iter()
+        .map(|town| town.number_of_buildings)
+        .max()
+        .unwrap_or(1)
+        .max(1) as f64;
+
+    svg.push_str("  <g id=\"towns\" font-size=\"13\">\n");
+    for town in &towns {
+        let (x, y) = point(town);
+
+        // Stops along the roads and points of interest are small unlabelled markers
+        if town.kind != TownKind::Town {
+            let fill = if town.kind.is_point_of_interest() {
+                "#703c8c"
             } else {
-                firstname = "NO DATA".into();
-            }
+                "#f4ecd8"
+            };
+            svg.push_str(&format!(
+                "    <g id=\"town-{}\">\n      <title>{} ({}, {}, population {})</title>\n      <rect x=\"{:.1}\" y=\"{:.1}\" width=\"6\" height=\"6\" fill=\"{}\" stroke=\"#3b2a1a\" stroke-width=\"1.5\" transform=\"rotate(45 {:.1} {:.1})\"/>\n    </g>\n",
+                town.id,
+                escape_xml(&town.name),
+                town.kind.name(),
+                escape_xml(&town.biome.0),
+                town.population,
+                x - 3.0,
+                y - 3.0,
+                fill,
+                x,
+                y
+            ));
+            continue;
         }
-        NpcSex::Unisex => {
-            if let Some(name) = names_unisex.get(rng.gen_range(0..names_unisex.len())) {
-                firstname = name.to_string();
-            } else {
-                firstname = "NO DATA".into();
-            }
+
+        let radius = 4.0 + 8.0 * (town.number_of_buildings as f64 / most_buildings).sqrt();
+        svg.push_str(&format!(
+            "    <g id=\"town-{}\">\n      <title>{} ({}, {}, population {})</title>\n      <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{:.1}\" fill=\"#b03a2e\" stroke=\"#3b2a1a\" stroke-width=\"1.5\"/>\n      <text x=\"{:.1}\" y=\"{:.1}\" fill=\"#1e1e1e\">{}</text>\n    </g>\n",
+            town.id,
+            escape_xml(&town.name),
+            escape_xml(&town.size.0),
+            escape_xml(&town.biome.0),
+            town.population,
+            x,
+            y,
+            radius,
+            x + radius + 3.0,
+            y + 4.0,
+            escape_xml(&town.name)
+        ));
+    }
+    svg.push_str("  </g>\n");
+
+    if settings.svg_town_insets {
+        svg.push_str("  <g id=\"town-insets\">\n");
+        for (i, town) in inset_towns.iter().enumerate() {
+            let x = INSET_GAP + (i % insets_per_row) as f64 * (INSET_SIZE + INSET_GAP);
+            let y = map_height + (i / insets_per_row) as f64 * (INSET_SIZE + INSET_GAP);
+            svg.push_str(&svg_town_inset(town, x, y, INSET_SIZE));
         }
+        svg.push_str("  </g>\n");
+    }
+
+    svg.push_str("</svg>\n");
+
+    let filepath = format!("{}/{}", settings.output_dir, filename);
+    fs::create_dir_all(settings.output_dir.clone())?;
+    fs::write(filepath, svg)?;
+
+    Ok("done!".into())
+}
+
+// Generate an SVG group drawing a town's square, streets and building plots in a square panel
+fn svg_town_inset(town: &Town, x: f64, y: f64, size: f64) -> String {
+    const TITLE_HEIGHT: f64 = 20.0;
+
+    let layout = &town.layout;
+    let scale = ((size - TITLE_HEIGHT) / layout.width.max(layout.height).max(1) as f64).min(4.0);
+    let offset_x = x + (size - layout.width as f64 * scale) / 2.0;
+    let offset_y = y + TITLE_HEIGHT;
+    let rect = |plot: &Plot| {
+        format!(
+            "x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"",
+            offset_x + plot.x as f64 * scale,
+            offset_y + plot.y as f64 * scale,
+            plot.width as f64 * scale,
+            plot.height as f64 * scale
+        )
     };
 
-    match building_type {
-        BuildingType::Residence => {
-            let surname = building_name.split([' ', '\'']).next().unwrap();
-            format!("{} {}", firstname, surname)
+    let mut svg = format!(
+        "    <g id=\"inset-{}\">\n      <rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#fbf7ee\" stroke=\"#8b6b4a\"/>\n      <text x=\"{:.1}\" y=\"{:.1}\" font-size=\"13\">{}</text>\n",
+        town.id,
+        x,
+        y,
+        size,
+        size,
+        x + 5.0,
+        y + 15.0,
+        escape_xml(&town.name)
+    );
+
+    for street in &layout.streets {
+        svg.push_str(&format!(
+            "      <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#d8c8a8\" stroke-width=\"{:.1}\"><title>{}</title></line>\n",
+            offset_x + street.start.0 as f64 * scale,
+            offset_y + street.start.1 as f64 * scale,
+            offset_x + street.end.0 as f64 * scale,
+            offset_y + street.end.1 as f64 * scale,
+            street.width as f64 * scale,
+            escape_xml(&street.name)
+        ));
+    }
+
+    svg.push_str(&format!(
+        "      <rect {} fill=\"#e0d2b4\"><title>{}</title></rect>\n",
+        rect(&layout.square.plot),
+        escape_xml(&layout.square.name)
+    ));
+
+    for building in &town.buildings {
+        svg.push_str(&format!(
+            "      <rect {} fill=\"#c9a27a\" stroke=\"#5a4632\" stroke-width=\"0.5\"><title>{} ({}), {}</title></rect>\n",
+            rect(&building.plot),
+            escape_xml(&building.name),
+            escape_xml(&building.building_type.0),
+            escape_xml(&building.address)
+        ));
+    }
+
+    svg.push_str("    </g>\n");
+    svg
+}
+
+// Function to escape text for use in XML
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Struct for an RGB image drawn in memory, used to render PNG maps
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    fn new(width: u32, height: u32, colour: [u8; 3]) -> Self {
+        Self {
+            width,
+            height,
+            // Sized in usize, as the number of pixels of a large image doesn't fit in a u32
+            pixels: vec![colour; width as usize * height as usize],
         }
-        BuildingType::Shop => {
-            let surname = building_name.split([' ', '\'']).next().unwrap();
-            format!("{} {}", firstname, surname)
+    }
+
+    fn set(&mut self, x: i64, y: i64, colour: [u8; 3]) {
+        if x >= 0 && y >= 0 && x < self.width as i64 && y < self.height as i64 {
+            self.pixels[y as usize * self.width as usize + x as usize] = colour;
         }
-        BuildingType::Tavern => {
-            if let Some(surname) = surnames.get(rng.gen_range(0..surnames.len())) {
-                format!("{} {}", firstname, surname)
-            } else {
-                format!("{} NO DATA", firstname)
+    }
+
+    fn fill_circle(&mut self, x: f64, y: f64, radius: f64, colour: [u8; 3]) {
+        let r = radius.ceil() as i64;
+        let (cx, cy) = (x.round() as i64, y.round() as i64);
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if ((dx * dx + dy * dy) as f64) <= radius * radius {
+                    self.set(cx + dx, cy + dy, colour);
+                }
             }
         }
-        BuildingType::Temple => {
-            format!("{} of the {}", firstname, building_name)
+    }
+
+    fn draw_line(&mut self, from: (f64, f64), to: (f64, f64), thickness: f64, colour: [u8; 3]) {
+        let steps = (to.0 - from.0)
+            .abs()
+            .max((to.1 - from.1).abs())
+            .ceil()
+            .max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            self.fill_circle(
+                from.0 + (to.0 - from.0) * t,
+                from.1 + (to.1 - from.1) * t,
+                thickness / 2.0,
+                colour,
+            );
         }
     }
-}
 
-// Generate rooms
-fn generate_rooms(
-    settings: &AppConfig,
-    rng: &mut StdRng,
-    id_tracker: &mut IdTracker,
-    town_id: &u32,
-    building_id: &u32,
-    npcs: &mut Vec<Npc>,
-) -> Vec<Room> {
-    let mut rooms = Vec::new();
+    fn draw_dashed_line(
+        &mut self,
+        from: (f64, f64),
+        to: (f64, f64),
+        thickness: f64,
+        dash: f64,
+        colour: [u8; 3],
+    ) {
+        let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+        let dashes = (length / dash).ceil().max(1.0) as usize;
+
+        for i in (0..dashes).step_by(2) {
+            let (start, end) = (
+                i as f64 / dashes as f64,
+                ((i + 1) as f64 / dashes as f64).min(1.0),
+            );
+            self.draw_line(
+                (
+                    from.0 + (to.0 - from.0) * start,
+                    from.1 + (to.1 - from.1) * start,
+                ),
+                (
+                    from.0 + (to.0 - from.0) * end,
+                    from.1 + (to.1 - from.1) * end,
+                ),
+                thickness,
+                colour,
+            );
+        }
+    }
 
-    let number_of_rooms = rng.gen_range(settings.min_rooms..settings.max_rooms);
+    // Draw text in upper case with a small pixel font, with an outline so it can be read on any background
+    fn draw_text(
+        &mut self,
+        x: i64,
+        y: i64,
+        text: &str,
+        scale: i64,
+        colour: [u8; 3],
+        outline: [u8; 3],
+    ) {
+        for (pass_colour, offsets) in [
+            (outline, &[(-1, 0), (1, 0), (0, -1), (0, 1)][..]),
+            (colour, &[(0, 0)][..]),
+        ] {
+            for (i, letter) in text.chars().enumerate() {
+                let rows = FONT
+                    .iter()
+                    .find(|(glyph, _)| *glyph == letter.to_ascii_uppercase())
+                    .map(|(_, rows)| *rows)
+                    .unwrap_or_default();
+                let left = x + i as i64 * 4 * scale;
+
+                for (row, bits) in rows.iter().enumerate() {
+                    for column in 0..3 {
+                        if bits & (4 >> column) == 0 {
+                            continue;
+                        }
+                        for (ox, oy) in offsets {
+                            for sy in 0..scale {
+                                for sx in 0..scale {
+                                    self.set(
+                                        left + column * scale + sx + ox,
+                                        y + row as i64 * scale + sy + oy,
+                                        pass_colour,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
 
-    for _ in 0..number_of_rooms {
-        let room_id = id_tracker.get_new_id(settings);
+    // Encode the image as a PNG file, stored without compression so no compression library is needed
+    fn encode_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.width as usize * 3 + 1) * self.height as usize);
+        for row in self.pixels.chunks(self.width as usize) {
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
 
-        rooms.push(Room {
-            id: room_id,
-            town_id: *town_id,
-            building_id: *building_id,
-            npcs: Vec::new(),
-            containers: generate_containers(
-                settings,
-                rng,
-                id_tracker,
-                town_id,
-                building_id,
-                &room_id,
-            ),
-        });
+        let mut zlib = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = raw.chunks(65535).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            let length = block.len() as u16;
+            zlib.push((i == blocks.len() - 1) as u8);
+            zlib.extend(length.to_le_bytes());
+            zlib.extend((!length).to_le_bytes());
+            zlib.extend(*block);
+        }
+        zlib.extend(adler32(&raw).to_be_bytes());
+
+        let mut header = Vec::new();
+        header.extend(self.width.to_be_bytes());
+        header.extend(self.height.to_be_bytes());
+        header.extend([8, 2, 0, 0, 0]);
+
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        png.extend(png_chunk(b"IHDR", &header));
+        png.extend(png_chunk(b"IDAT", &zlib));
+        png.extend(png_chunk(b"IEND", &[]));
+        png
     }
+}
 
-    npcs.shuffle(rng);
+// 3x5 pixel font for map labels, each row's bits are the columns from left to right
+const FONT: [(char, [u8; 5]); 40] = [
+    ('A', [2, 5, 7, 5, 5]),
+    ('B', [6, 5, 6, 5, 6]),
+    ('C', [3, 4, 4, 4, 3]),
+    ('D', [6, 5, 5, 5, 6]),
+    ('E', [7, 4, 6, 4, 7]),
+    ('F', [7, 4, 6, 4, 4]),
+    ('G', [3, 4, 5, 5, 3]),
+    ('H', [5, 5, 7, 5, 5]),
+    ('I', [7, 2, 2, 2, 7]),
+    ('J', [1, 1, 1, 5, 2]),
+    ('K', [5, 5, 6, 5, 5]),
+    ('L', [4, 4, 4, 4, 7]),
+    ('M', [5, 7, 7, 5, 5]),
+    ('N', [6, 5, 5, 5, 5]),
+    ('O', [2, 5, 5, 5, 2]),
+    ('P', [6, 5, 6, 4, 4]),
+    ('Q', [2, 5, 5, 6, 3]),
+    ('R', [6, 5, 6, 5, 5]),
+    ('S', [3, 4, 2, 1, 6]),
+    ('T', [7, 2, 2, 2, 2]),
+    ('U', [5, 5, 5, 5, 7]),
+    ('V', [5, 5, 5, 5, 2]),
+    ('W', [5, 5, 7, 7, 5]),
+    ('X', [5, 5, 2, 5, 5]),
+    ('Y', [5, 5, 2, 2, 2]),
+    ('Z', [7, 1, 2, 4, 7]),
+    ('0', [7, 5, 5, 5, 7]),
+    ('1', [2, 6, 2, 2, 7]),
+    ('2', [6, 1, 2, 4, 7]),
+    ('3', [6, 1, 2, 1, 6]),
+    ('4', [5, 5, 7, 1, 1]),
+    ('5', [7, 4, 6, 1, 6]),
+    ('6', [3, 4, 7, 5, 7]),
+    ('7', [7, 1, 2, 2, 2]),
+    ('8', [7, 5, 7, 5, 7]),
+    ('9', [7, 5, 7, 1, 6]),
+    ('\'', [2, 2, 0, 0, 0]),
+    ('-', [0, 0, 7, 0, 0]),
+    ('.', [0, 0, 0, 0, 2]),
+    (',', [0, 0, 0, 2, 4]),
+];
+
+// Function to build a PNG chunk from its type and data
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend((data.len() as u32).to_be_bytes());
+    chunk.extend(kind);
+    chunk.extend(data);
+    chunk.extend(crc32(&chunk[4..]).to_be_bytes());
+    chunk
+}
 
-    for mut npc in npcs.drain(..) {
-        if let Some(room) = rooms.choose_mut(rng) {
-            npc.room_id = Some(room.id);
-            room.npcs.push(npc);
+// Function to calculate the CRC-32 checksum used by PNG chunks
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
         }
     }
+    !crc
+}
 
-    rooms
+// Function to calculate the Adler-32 checksum used by zlib streams
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
 }
 
-// Generate containers
-fn generate_containers(
+// Function to get the colour of the land at a point in the world, from its biome and shaded by the slope
+// of the land. Land near towns is farmed
+fn terrain_colour(
     settings: &AppConfig,
-    rng: &mut StdRng,
-    id_tracker: &mut IdTracker,
-    town_id: &u32,
-    building_id: &u32,
-    room_id: &u32,
-) -> Vec<Container> {
-    let mut containers = Vec::new();
+    terrain: &Terrain,
+    towns: &[&Town],
+    x: f64,
+    y: f64,
+) -> [u8; 3] {
+    const FIELDS: [f64; 3] = [214.0, 204.0, 140.0];
+    const FIELD_RADIUS: f64 = 8.0;
+
+    if terrain.lakes.iter().any(|lake| lake.contains(x, y)) {
+        return water_colour(settings);
+    }
 
-    let num_of_containers = rng.gen_range(settings.min_containers..settings.max_containers);
+    let biome = terrain.biome(settings, x, y);
+    let colour = biome.colour.map(|channel| channel as f64);
 
-    for _ in 0..num_of_containers {
-        let container_id = id_tracker.get_new_id(settings);
+    // Light falls from the north west
+    let slope = terrain.elevation(x, y) - terrain.elevation(x - 1.0, y - 1.0);
+    let shade = if biome.water {
+        1.0
+    } else {
+        (1.0 + slope * 12.0).clamp(0.75, 1.25)
+    };
 
-        let container_type = match rng.gen_range(0..ContainerType::COUNT) {
-            0 => ContainerType::Barrel,
-            1 => ContainerType::Crate,
-            2 => ContainerType::Chest,
-            _ => ContainerType::Barrel,
-        };
+    let nearest = towns
+        .iter()
+        .map(|town| {
+            let (dx, dy) = (town.coords.0 as f64 - x, town.coords.1 as f64 - y);
+            (dx * dx + dy * dy).sqrt() - (town.number_of_buildings as f64).sqrt()
+        })
+        .fold(f64::INFINITY, f64::min);
+    let farmed = if biome.water {
+        0.0
+    } else {
+        (1.0 - nearest / FIELD_RADIUS).clamp(0.0, 1.0) * 0.6
+    };
 
-        containers.push(Container {
-            id: container_id,
-            container_type,
-            town_id: *town_id,
-            building_id: *building_id,
-            room_id: *room_id,
-        });
-    }
+    [0, 1, 2].map(|i| ((colour[i] + (FIELDS[i] - colour[i]) * farmed) * shade).min(255.0) as u8)
+}
 
-    containers
+// Function to get the colour rivers and lakes are drawn in, the colour of the first water biome
+fn water_colour(settings: &AppConfig) -> [u8; 3] {
+    settings
+        .biomes
+        .iter()
+        .find(|biome| biome.water)
+        .map(|biome| biome.colour)
+        .unwrap_or([70, 110, 170])
 }
 
-// Generate a graph with towns and edges using Kruskal’s Algorithm
-fn generate_graph(
+// Render a raster map of the world with terrain, roads and towns, scaled to the given width in pixels
+fn render_world_png(
     settings: &AppConfig,
-    rng: &mut StdRng,
-    towns: Vec<Town>,
-) -> (Graph<Town, JourneyInfo>, Vec<NodeIndex>) {
-    print!("Generating graph... ");
-
-    let mut town_graph = Graph::<Town, JourneyInfo>::new();
-    let mut town_nodes = Vec::new();
+    graph: &Graph<Town, JourneyInfo>,
+    terrain: &Terrain,
+    width: u32,
+) -> Image {
+    const MARGIN: f64 = 40.0;
 
-    for town in towns {
-        let node = town_graph.add_node(town);
-        town_nodes.push(node);
-    }
+    let width = width.max(200);
+    let towns: Vec<&Town> = graph.node_weights().collect();
 
-    // Create a lookup table for constant-time index retrieval
-    let node_map: HashMap<NodeIndex, usize> = town_nodes
+    let min_x = towns
         .iter()
-        .enumerate()
-        .map(|(i, &n)| (n, i))
-        .collect();
-
-    let mut town_pairs: Vec<(NodeIndex, NodeIndex, u32)> = town_nodes
+        .map(|town| town.coords.0)
+        .min()
+        .unwrap_or_default() as f64;
+    let min_y = towns
         .iter()
-        .tuple_combinations()
-        .map(|(&t1, &t2)| {
-            let distance = rng.gen_range(settings.min_distance..settings.max_distance);
-            (t1, t2, distance)
-        })
-        .collect();
-
-    town_pairs.sort_unstable_by_key(|&(_, _, dist)| dist);
+        .map(|town| town.coords.1)
+        .min()
+        .unwrap_or_default() as f64;
+    let max_x = towns
+        .iter()
+        .map(|town| town.coords.0)
+        .max()
+        .unwrap_or_default() as f64;
+    let max_y = towns
+        .iter()
+        .map(|town| town.coords.1)
+        .max()
+        .unwrap_or_default() as f64;
+
+    let scale = (width as f64 - MARGIN * 2.0) / (max_x - min_x).max(1.0);
+    let height = (((max_y - min_y) * scale + MARGIN * 2.0).ceil() as u32)
+        .clamp(100, width.saturating_mul(4));
+    let point = |town: &Town| {
+        (
+            (town.coords.0 as f64 - min_x) * scale + MARGIN,
+            (town.coords.1 as f64 - min_y) * scale + MARGIN,
+        )
+    };
 
-    let mut uf = UnionFind::new(town_nodes.len());
-    let mut edges_added = 0;
+    let mut image = Image::new(width, height, [0, 0, 0]);
+    for y in 0..height {
+        for x in 0..width {
+            let world_x = (x as f64 - MARGIN) / scale + min_x;
+            let world_y = (y as f64 - MARGIN) / scale + min_y;
+            image.set(
+                x as i64,
+                y as i64,
+                terrain_colour(settings, terrain, &towns, world_x, world_y),
+            );
+        }
+    }
 
-    for (town1, town2, distance) in &town_pairs {
-        let idx1 = *node_map.get(town1).expect("Town1 not found in node_map");
-        let idx2 = *node_map.get(town2).expect("Town2 not found in node_map");
+    let detail = (width as f64 / 800.0).max(1.0);
 
-        if uf.union(idx1, idx2) {
-            let cost = settings.cost * distance;
-            town_graph.add_edge(
-                *town1,
-                *town2,
-                JourneyInfo {
-                    distance: *distance,
-                    cost,
-                },
+    for river in &terrain.rivers {
+        for segment in river.points.windows(2) {
+            let from = (
+                (segment[0].0 as f64 - min_x) * scale + MARGIN,
+                (segment[0].1 as f64 - min_y) * scale + MARGIN,
             );
-            edges_added += 1;
+            let to = (
+                (segment[1].0 as f64 - min_x) * scale + MARGIN,
+                (segment[1].1 as f64 - min_y) * scale + MARGIN,
+            );
+            image.draw_line(from, to, 3.0 * detail, water_colour(settings));
         }
     }
 
-    for (town1, town2, distance) in town_pairs
-        .into_iter()
-        .skip(edges_added)
-        .take(settings.num_of_connections as usize - edges_added)
-    {
-        let cost = settings.cost * distance;
-        town_graph.add_edge(town1, town2, JourneyInfo { distance, cost });
+    for edge in graph.edge_references() {
+        let from = point(&graph[edge.source()]);
+        let to = point(&graph[edge.target()]);
+
+        match edge.weight().route {
+            RouteKind::Road => {
+                image.draw_line(from, to, 4.0 * detail, [92, 64, 38]);
+                image.draw_line(from, to, 2.0 * detail, [196, 160, 110]);
+            }
+            RouteKind::River | RouteKind::Sea => {
+                image.draw_dashed_line(from, to, 2.0 * detail, 8.0 * detail, [24, 48, 96]);
+            }
+            RouteKind::Trail => {
+                image.draw_dashed_line(from, to, 1.5 * detail, 4.0 * detail, [92, 64, 38]);
+            }
+        }
     }
 
-    println!("done!");
+    let most_buildings = towns
+        .iter()
+        .map(|town| town.number_of_buildings)
+        .max()
+        .unwrap_or(1)
+        .max(1) as f64;
+
+    for town in &towns {
+        let (x, y) = point(town);
+
+        // Stops along the roads and points of interest are small unlabelled markers
+        if town.kind != TownKind::Town {
+            let colour = if town.kind.is_point_of_interest() {
+                [112, 60, 140]
+            } else {
+                [244, 236, 216]
+            };
+            image.fill_circle(x, y, 3.0 * detail, [40, 28, 20]);
+            image.fill_circle(x, y, 2.0 * detail, colour);
+            continue;
+        }
 
-    (town_graph, town_nodes)
+        let radius =
+            (3.0 + 6.0 * (town.number_of_buildings as f64 / most_buildings).sqrt()) * detail;
+        image.fill_circle(x, y, radius + detail, [40, 28, 20]);
+        image.fill_circle(x, y, radius, [176, 58, 46]);
+    }
+
+    let text_scale = detail.round() as i64 * 2;
+    for town in towns.iter().filter(|town| town.kind == TownKind::Town) {
+        let (x, y) = point(town);
+        let radius =
+            (3.0 + 6.0 * (town.number_of_buildings as f64 / most_buildings).sqrt()) * detail;
+
+        // Put the name on the left of towns near the right edge so it isn't cut off
+        let label_width = (town.name.chars().count() as i64 * 4 * text_scale) as f64;
+        let label_x = if x + radius + label_width < width as f64 {
+            x + radius + 2.0 * detail
+        } else {
+            x - radius - 2.0 * detail - label_width
+        };
+
+        image.draw_text(
+            label_x as i64,
+            y as i64 - text_scale * 5 / 2,
+            &town.name,
+            text_scale,
+            [20, 20, 20],
+            [250, 246, 236],
+        );
+    }
+
+    image
 }
 
-// Save graph to a DOT file
-fn save_graph(
+// Save a raster map of the world to a PNG file
+fn save_png(
     settings: &AppConfig,
     graph: &Graph<Town, JourneyInfo>,
+    terrain: &Terrain,
     filename: &str,
 ) -> Result<String, std::io::Error> {
-    print!("Saving graph to file: \"{}\"... ", filename);
-
-    let mut dot_output = String::from("graph Towns {\n");
-
-    for edge in graph.edge_references() {
-        let source_town = &graph[edge.source()];
-        let target_town = &graph[edge.target()];
-        let journey_info = edge.weight();
-
-        dot_output.push_str(&format!(
-            "    \"{}\" -- \"{}\" [label=\"{} m / {} gold\", len={}];\n",
-            source_town.name,
-            target_town.name,
-            journey_info.distance,
-            journey_info.cost,
-            journey_info.distance / 10
-        ));
-    }
+    print!("Saving map to file: \"{}\"... ", filename);
 
-    dot_output.push_str("}\n");
+    let image = render_world_png(settings, graph, terrain, settings.png_width);
 
     let filepath = format!("{}/{}", settings.output_dir, filename);
     fs::create_dir_all(settings.output_dir.clone())?;
-    fs::write(filepath, dot_output)?;
+    fs::write(filepath, image.encode_png())?;
 
     Ok("done!".into())
 }
@@ -842,13 +6523,498 @@ fn save_world(
     Ok("done!".into())
 }
 
+// Load a world from a JSON file
+fn load_world(settings: &AppConfig, filename: &str) -> Result<World, std::io::Error> {
+    print!("Loading world from file: \"{}\"... ", filename);
+    io::stdout().flush()?;
+
+    let filepath = format!("{}/{}", settings.output_dir, filename);
+
+    let file_content = fs::read_to_string(filepath)?;
+    let world = serde_json::from_str(&file_content)?;
+
+    println!("done!");
+
+    Ok(world)
+}
+
+// Struct for the characters used to draw maps
+struct MapCharset {
+    top_left: char,
+    top_right: char,
+    bottom_left: char,
+    bottom_right: char,
+    horizontal: char,
+    vertical: char,
+    street: char,
+    square: char,
+    diagonal_up: char,
+    diagonal_down: char,
+    water: char,
+    trail: char,
+    stop: char,
+    point_of_interest: char,
+    towns: [char; 4],
+}
+
+const UNICODE_CHARSET: MapCharset = MapCharset {
+    top_left: '┌',
+    top_right: '┐',
+    bottom_left: '└',
+    bottom_right: '┘',
+    horizontal: '─',
+    vertical: '│',
+    street: '░',
+    square: '▒',
+    diagonal_up: '╱',
+    diagonal_down: '╲',
+    water: '≈',
+    trail: ':',
+    stop: '◇',
+    point_of_interest: '▲',
+    towns: ['·', '•', '●', '◉'],
+};
+
+const ASCII_CHARSET: MapCharset = MapCharset {
+    top_left: '+',
+    top_right: '+',
+    bottom_left: '+',
+    bottom_right: '+',
+    horizontal: '-',
+    vertical: '|',
+    street: '.',
+    square: ':',
+    diagonal_up: '/',
+    diagonal_down: '\\',
+    water: '~',
+    trail: ':',
+    stop: '+',
+    point_of_interest: '^',
+    towns: ['.', 'o', 'O', '@'],
+};
+
+// Struct for a grid of characters that maps are drawn on
+struct CharGrid {
+    cells: Vec<Vec<char>>,
+}
+
+impl CharGrid {
+    fn new(columns: usize, rows: usize) -> Self {
+        Self {
+            cells: vec![vec![' '; columns]; rows],
+        }
+    }
+
+    fn set(&mut self, column: i64, row: i64, c: char) {
+        if column < 0 || row < 0 {
+            return;
+        }
+        if let Some(cell) = self
+            .cells
+            .get_mut(row as usize)
+            .and_then(|cells| cells.get_mut(column as usize))
+        {
+            *cell = c;
+        }
+    }
+
+    fn fill(&mut self, left: i64, top: i64, right: i64, bottom: i64, c: char) {
+        for row in top..=bottom {
+            for column in left..=right {
+                self.set(column, row, c);
+            }
+        }
+    }
+
+    fn write(&mut self, column: i64, row: i64, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            self.set(column + i as i64, row, c);
+        }
+    }
+
+    fn write_vertical(&mut self, column: i64, row: i64, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            self.set(column, row + i as i64, c);
+        }
+    }
+
+    fn draw_box(&mut self, left: i64, top: i64, right: i64, bottom: i64, charset: &MapCharset) {
+        for column in left..=right {
+            self.set(column, top, charset.horizontal);
+            self.set(column, bottom, charset.horizontal);
+        }
+        for row in top..=bottom {
+            self.set(left, row, charset.vertical);
+            self.set(right, row, charset.vertical);
+        }
+        self.set(left, top, charset.top_left);
+        self.set(right, top, charset.top_right);
+        self.set(left, bottom, charset.bottom_left);
+        self.set(right, bottom, charset.bottom_right);
+    }
+
+    fn get(&self, column: i64, row: i64) -> Option<char> {
+        if column < 0 || row < 0 {
+            return None;
+        }
+        self.cells
+            .get(row as usize)
+            .and_then(|cells| cells.get(column as usize))
+            .copied()
+    }
+
+    fn render(&self) -> String {
+        self.cells
+            .iter()
+            .map(|row| row.iter().collect::<String>().trim_end().to_string())
+            .join("\n")
+    }
+}
+
+// Render a map of a town's layout with a legend, buildings are labelled with their number in the legend
+fn render_town_map(settings: &AppConfig, town: &Town) -> String {
+    let charset = if settings.ascii_maps {
+        &ASCII_CHARSET
+    } else {
+        &UNICODE_CHARSET
+    };
+    let layout = &town.layout;
+
+    // Characters are about twice as tall as they are wide
+    let scale_x = layout.width.div_ceil(settings.map_width.max(10)).max(1);
+    let scale_y = scale_x * 2;
+
+    let columns = (layout.width / scale_x + 1) as usize;
+    let rows = (layout.height / scale_y + 1) as usize;
+    let mut grid = CharGrid::new(columns, rows);
+
+    // Convert a rectangle in metres to the grid cells it covers
+    let cells = |plot: &Plot| {
+        let left = (plot.x / scale_x) as i64;
+        let top = (plot.y / scale_y) as i64;
+        let right = ((plot.x + plot.width) / scale_x).saturating_sub(1) as i64;
+        let bottom = ((plot.y + plot.height) / scale_y).saturating_sub(1) as i64;
+        (left, top, right.max(left), bottom.max(top))
+    };
+
+    for street in &layout.streets {
+        let (x1, y1) = (street.start.0 / scale_x, street.start.1 / scale_y);
+        let (x2, y2) = (street.end.0 / scale_x, street.end.1 / scale_y);
+        let (left, right) = (x1.min(x2) as i64, x1.max(x2) as i64);
+        let (top, bottom) = (y1.min(y2) as i64, y1.max(y2) as i64);
+
+        // Streets run either east-west or north-south
+        let half_width = street.width / 2;
+        if y1 == y2 {
+            let band = (
+                street.start.1.saturating_sub(half_width) / scale_y,
+                (street.start.1 + half_width).saturating_sub(1) / scale_y,
+            );
+            grid.fill(left, band.0 as i64, right, band.1 as i64, charset.street);
+        } else {
+            let band = (
+                street.start.0.saturating_sub(half_width) / scale_x,
+                (street.start.0 + half_width).saturating_sub(1) / scale_x,
+            );
+            grid.fill(band.0 as i64, top, band.1 as i64, bottom, charset.street);
+        }
+
+        // Label the street along its length if there's room
+        let length = (right - left).max(bottom - top);
+        if (street.name.chars().count() as i64) < length {
+            if y1 == y2 {
+                grid.write(left + 1, top, &street.name);
+            } else {
+                grid.write_vertical(left, top + 1, &street.name);
+            }
+        }
+    }
+
+    let (left, top, right, bottom) = cells(&layout.square.plot);
+    grid.fill(left, top, right, bottom, charset.square);
+    let name_length = layout.square.name.chars().count() as i64;
+    if name_length <= right - left + 1 {
+        grid.write(
+            left + (right - left + 1 - name_length) / 2,
+            (top + bottom) / 2,
+            &layout.square.name,
+        );
+    }
+
+    for (i, building) in town.buildings.iter().enumerate() {
+        let (left, top, right, bottom) = cells(&building.plot);
+        grid.draw_box(left, top, right, bottom, charset);
+
+        let label = (i + 1).to_string();
+        let label_length = label.len() as i64;
+        let column = if label_length < right - left {
+            left + (right - left + 1 - label_length) / 2
+        } else {
+            left
+        };
+        grid.write(column, (top + bottom) / 2, &label);
+    }
+
+    let mut output = format!(
+        "{} ({}, population {})\n\n{}\n\nScale: 1 column = {} m, 1 row = {} m, north is up\n\nLegend:\n",
+        town.name,
+        town.size.0,
+        town.population,
+        grid.render(),
+        scale_x,
+        scale_y
+    );
+
+    for (i, building) in town.buildings.iter().enumerate() {
+        output.push_str(&format!(
+            "{:>4}  ID {:<7} {} ({}), {}, {}\n",
+            i + 1,
+            building.id,
+            building.name,
+            building.building_type.0,
+            building.address,
+            building.district
+        ));
+    }
+
+    output.push_str(&format!(
+        "\n{}  {}\n{}  Streets\n",
+        charset.square, layout.square.name, charset.street
+    ));
+
+    output
+}
+
+// Render a map of the whole world showing towns and the roads between them, scaled to fit the given width.
+// Towns are labelled with their name where there's room, otherwise with their number in the legend
+fn render_world_map(
+    settings: &AppConfig,
+    graph: &Graph<Town, JourneyInfo>,
+    columns: u32,
+) -> String {
+    let charset = if settings.ascii_maps {
+        &ASCII_CHARSET
+    } else {
+        &UNICODE_CHARSET
+    };
+
+    let places: Vec<&Town> = graph.node_weights().collect();
+    if places.is_empty() {
+        return "The world has no towns.".into();
+    }
+
+    // Stops along the roads and points of interest are marked on the map, but not labelled or listed in the
+    // legend
+    let (towns, stops): (Vec<&Town>, Vec<&Town>) =
+        places.iter().partition(|town| town.kind == TownKind::Town);
+
+    let min_x = places
+        .iter()
+        .map(|town| town.coords.0)
+        .min()
+        .unwrap_or_default();
+    let min_y = places
+        .iter()
+        .map(|town| town.coords.1)
+        .min()
+        .unwrap_or_default();
+    let max_x = places
+        .iter()
+        .map(|town| town.coords.0)
+        .max()
+        .unwrap_or_default();
+    let max_y = places
+        .iter()
+        .map(|town| town.coords.1)
+        .max()
+        .unwrap_or_default();
+
+    // Characters are about twice as tall as they are wide
+    let columns = columns.max(20);
+    let scale_x = ((max_x - min_x).max(1) as f64 / (columns - 1) as f64).max(0.1);
+    let scale_y = scale_x * 2.0;
+    let rows = ((max_y - min_y) as f64 / scale_y).round() as usize + 1;
+
+    let mut grid = CharGrid::new(columns as usize, rows);
+    let cell = |town: &Town| {
+        (
+            ((town.coords.0 - min_x) as f64 / scale_x).round() as i64,
+            ((town.coords.1 - min_y) as f64 / scale_y).round() as i64,
+        )
+    };
+
+    for edge in graph.edge_references() {
+        let (x1, y1) = cell(&graph[edge.source()]);
+        let (x2, y2) = cell(&graph[edge.target()]);
+        let (dx, dy) = (x2 - x1, y2 - y1);
+
+        let road = if edge.weight().route == RouteKind::Trail {
+            charset.trail
+        } else if edge.weight().route != RouteKind::Road {
+            charset.water
+        } else if dy.abs() * 2 < dx.abs() {
+            charset.horizontal
+        } else if dx.abs() * 2 < dy.abs() {
+            charset.vertical
+        } else if (dx > 0) == (dy > 0) {
+            charset.diagonal_down
+        } else {
+            charset.diagonal_up
+        };
+
+        let steps = dx.abs().max(dy.abs()).max(1);
+        for step in 0..=steps {
+            let column = x1 + (dx as f64 * step as f64 / steps as f64).round() as i64;
+            let row = y1 + (dy as f64 * step as f64 / steps as f64).round() as i64;
+            grid.set(column, row, road);
+        }
+    }
+
+    let most_buildings = towns
+        .iter()
+        .map(|town| town.number_of_buildings)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+
+    let mut occupied: HashSet<(i64, i64)> = HashSet::new();
+    for stop in &stops {
+        let (column, row) = cell(stop);
+        if stop.kind.is_point_of_interest() {
+            grid.set(column, row, charset.point_of_interest);
+        } else {
+            grid.set(column, row, charset.stop);
+        }
+        occupied.insert((column, row));
+    }
+    for town in &towns {
+        let (column, row) = cell(town);
+        let size = (town.number_of_buildings * 4)
+            .div_ceil(most_buildings)
+            .clamp(1, 4)
+            - 1;
+        grid.set(column, row, charset.towns[size as usize]);
+        occupied.insert((column, row));
+    }
+
+    // Label the largest towns first so they get their names on the map
+    let mut order: Vec<usize> = (0..towns.len()).collect();
+    order.sort_by(|&a, &b| {
+        towns[b]
+            .number_of_buildings
+            .cmp(&towns[a].number_of_buildings)
+            .then(a.cmp(&b))
+    });
+
+    for i in order {
+        let (column, row) = cell(towns[i]);
+        let number = (i + 1).to_string();
+
+        let placements = [
+            (towns[i].name.as_str(), column + 2),
+            (
+                towns[i].name.as_str(),
+                column - towns[i].name.chars().count() as i64 - 1,
+            ),
+            (number.as_str(), column + 1),
+            (number.as_str(), column - number.len() as i64),
+        ];
+
+        for (label, start) in placements {
+            let end = start + label.chars().count() as i64;
+            let fits =
+                (start..end).all(|c| grid.get(c, row).is_some() && !occupied.contains(&(c, row)));
+
+            if fits {
+                grid.write(start, row, label);
+                occupied.extend((start - 1..=end).map(|c| (c, row)));
+                break;
+            }
+        }
+    }
+
+    let mut output = format!(
+        "{}\n\nScale: 1 column = {:.1} distance, 1 row = {:.1} distance, north is up\n\nLegend:\n",
+        grid.render(),
+        scale_x,
+        scale_y
+    );
+
+    for (i, town) in towns.iter().enumerate() {
+        output.push_str(&format!(
+            "{:>4}  {} ({}, {}, {} buildings, population {})\n",
+            i + 1,
+            town.name,
+            town.size.0,
+            town.biome.0,
+            town.number_of_buildings,
+            town.population
+        ));
+    }
+
+    output.push_str(&format!(
+        "\n{} {} {} {}  Towns, by number of buildings\n",
+        charset.towns[0], charset.towns[1], charset.towns[2], charset.towns[3]
+    ));
+    if stops.iter().any(|stop| !stop.kind.is_point_of_interest()) {
+        output.push_str(&format!(
+            "{}        Crossroads, waystations, bridges and fords\n",
+            charset.stop
+        ));
+    }
+    if stops.iter().any(|stop| stop.kind.is_point_of_interest()) {
+        output.push_str(&format!(
+            "{}        Ruins, caves, shrines, bandit camps and dungeons\n",
+            charset.point_of_interest
+        ));
+    }
+
+    output
+}
+
+// Function to get the width of the terminal, falls back to the map width setting
+fn terminal_width(settings: &AppConfig) -> u32 {
+    match crossterm::terminal::size() {
+        Ok((columns, _)) if columns > 1 => columns as u32 - 1,
+        _ => settings.map_width,
+    }
+}
+
+// Save a rendered map to a text file
+fn save_map(settings: &AppConfig, map: &str, filename: &str) -> Result<String, std::io::Error> {
+    print!("Saving map to file: \"{}\"... ", filename);
+
+    let filepath = format!("{}/{}", settings.output_dir, filename);
+    fs::create_dir_all(settings.output_dir.clone())?;
+    fs::write(filepath, map)?;
+
+    Ok("done!".into())
+}
+
+// Function to turn a name into something safe to use in a file name
+fn file_name_from(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
 // Import a DOT file and generate Towns and Graph
 fn import(settings: &AppConfig, filename: &str, seed: u64) -> ImportResult {
-    let imported_raw_graph = load_dot(settings, filename)?;
+    let mut imported_raw_graph = load_dot(settings, filename)?;
+
+    let (towns, mut world) =
+        generate_world_from_imported_raw_graph(settings, &mut imported_raw_graph, seed)?;
+    let mut graph = generate_graph_from_imported_towns(&imported_raw_graph, &towns);
 
-    let (towns, world) =
-        generate_world_from_imported_raw_graph(settings, &imported_raw_graph, seed);
-    let graph = generate_graph_from_imported_towns(&imported_raw_graph, &towns);
+    classify_routes(settings, &mut graph, &world.terrain);
+
+    world.roads = roads_from_graph(&graph);
 
     Ok((graph, towns, world))
 }
@@ -873,15 +7039,27 @@ fn load_dot(
     let mut node_indices = HashMap::new();
 
     for line in file_content.lines() {
+        if let Some((name, kind)) = parse_node_line(line) {
+            let index = *node_indices.entry(name.clone()).or_insert_with(|| {
+                graph.add_node(TownRaw {
+                    name: name.clone(),
+                    kind,
+                })
+            });
+            graph[index].kind = kind;
+        }
+
         if let Some((source, target, label)) = parse_edge_line(line) {
             let src_index = *node_indices.entry(source.clone()).or_insert_with(|| {
                 graph.add_node(TownRaw {
                     name: source.clone(),
+                    kind: TownKind::Town,
                 })
             });
             let tgt_index = *node_indices.entry(target.clone()).or_insert_with(|| {
                 graph.add_node(TownRaw {
                     name: target.clone(),
+                    kind: TownKind::Town,
                 })
             });
 
@@ -896,6 +7074,24 @@ fn load_dot(
     Ok(graph)
 }
 
+// Parse a node line of a DOT file, returns the name and kind of the stops along the roads
+fn parse_node_line(line: &str) -> Option<(String, TownKind)> {
+    let line = line.trim();
+
+    if line.starts_with('"') && !line.contains("--") {
+        let name_end = line[1..].find('"')? + 1;
+        let name = line[1..name_end].to_string();
+
+        let kind_start = line.find("kind=\"")? + 6;
+        let kind_end = line[kind_start..].find('"')? + kind_start;
+        let kind =
+            TownKind::iter().find(|kind| format!("{:?}", kind) == line[kind_start..kind_end])?;
+
+        return Some((name, kind));
+    }
+    None
+}
+
 // Parse a DOT file
 fn parse_edge_line(line: &str) -> Option<(String, String, String)> {
     let line = line.trim();
@@ -926,16 +7122,17 @@ fn parse_edge_line(line: &str) -> Option<(String, String, String)> {
     None
 }
 
-// Generate a world from a loaded in DOT file
+// Generate a world from a loaded in DOT file, routes by boat are added to the graph between its ports
 fn generate_world_from_imported_raw_graph(
     settings: &AppConfig,
-    graph: &Graph<TownRaw, JourneyInfo>,
+    graph: &mut Graph<TownRaw, JourneyInfo>,
     seed: u64,
-) -> (Vec<Town>, World) {
+) -> Result<(Vec<Town>, World), std::io::Error> {
     let mut rng = StdRng::seed_from_u64(seed);
     let mut id_tracker = IdTracker::new(seed);
 
-    let towns = generate_towns_from_imported_raw_graph(settings, &mut rng, &mut id_tracker, graph);
+    let (towns, terrain, network) =
+        generate_towns_from_imported_raw_graph(settings, &mut rng, &mut id_tracker, graph)?;
 
     print!("Generating world... ");
 
@@ -945,6 +7142,10 @@ fn generate_world_from_imported_raw_graph(
         rooms: HashMap::new(),
         npcs: HashMap::new(),
         containers: HashMap::new(),
+        roads: Vec::new(),
+        terrain,
+        network,
+        dungeons: HashMap::new(),
     };
 
     world.towns = towns.iter().map(|town| (town.id, town.clone())).collect();
@@ -992,44 +7193,144 @@ fn generate_world_from_imported_raw_graph(
         })
         .collect();
 
+    world.dungeons = towns
+        .iter()
+        .flat_map(|town| {
+            town.buildings.iter().filter_map(|building| {
+                building
+                    .dungeon
+                    .as_ref()
+                    .map(|dungeon| (building.id, dungeon.clone()))
+            })
+        })
+        .collect();
+
     println!("done!");
 
-    (towns, world)
+    Ok((towns, world))
 }
 
-// Generate towns from a loaded in DOT file
+// Generate towns from a loaded in DOT file, routes by boat are added to the graph between its ports
 fn generate_towns_from_imported_raw_graph(
     settings: &AppConfig,
     rng: &mut StdRng,
     id_tracker: &mut IdTracker,
-    graph: &Graph<TownRaw, JourneyInfo>,
-) -> Vec<Town> {
+    graph: &mut Graph<TownRaw, JourneyInfo>,
+) -> Result<(Vec<Town>, Terrain, NetworkMetrics), std::io::Error> {
+    let raw_towns: Vec<TownRaw> = graph.node_weights().cloned().collect();
+    let town_coords = generate_town_coords(rng, &*graph);
+    let (terrain, placements) = place_towns_on_terrain(settings, rng, &town_coords);
+    let coords: Vec<(u32, u32)> = placements.iter().map(|(coords, _)| *coords).collect();
+    let water_map = WaterMap::new(settings, &terrain, &coords);
+    let harbours = find_harbours(settings, &terrain, &water_map, &coords);
+
+    // Files that already have routes by boat keep them as they are
+    if graph
+        .edge_weights()
+        .all(|journey_info| !matches!(journey_info.route, RouteKind::River | RouteKind::Sea))
+    {
+        add_water_routes(settings, graph, &coords, &terrain, &water_map, &harbours);
+    }
+    let (metrics, mut network) = analyse_network(&*graph);
+    let kinds: Vec<TownKind> = raw_towns.iter().map(|town| town.kind).collect();
+    let capital = choose_capital(settings, &metrics, &kinds);
+
     print!("Generating towns... ");
 
-    let town_names: Vec<String> = graph.node_weights().map(|town| town.name.clone()).collect();
+    let grammar = load_grammar(settings);
+    let lists = load_name_lists(settings);
 
     let mut towns = Vec::new();
 
-    for townname in town_names {
+    for (index, (((raw_town, (coords, biome)), harbour), metrics)) in raw_towns
+        .into_iter()
+        .zip(placements)
+        .zip(&harbours)
+        .zip(metrics)
+        .enumerate()
+    {
+        // Stops along the roads and points of interest keep their kind, with the buildings it always has
+        if let Some(building_types) = place_building_types(settings, raw_town.kind) {
+            let mut town = generate_place(
+                settings,
+                rng,
+                id_tracker,
+                &lists,
+                &grammar,
+                raw_town.kind,
+                &building_types,
+                coords,
+                biome,
+                &mut HashSet::new(),
+            );
+            // Buildings named after the place follow it to the name from the DOT file
+            let generated_name = std::mem::replace(&mut town.name, raw_town.name);
+            for building in &mut town.buildings {
+                if building.name == generated_name {
+                    building.name = town.name.clone();
+                    if let Some(dungeon) = &mut building.dungeon {
+                        dungeon.name = town.name.clone();
+                    }
+                }
+            }
+            town.metrics = metrics;
+            towns.push(town);
+            continue;
+        }
+
         let town_id = id_tracker.get_new_id(settings);
+        let culture = choose_town_culture(settings, rng);
 
-        let number_of_buildings = rng.gen_range(settings.min_buildings..settings.max_buildings);
+        let is_capital = capital == Some(index);
+        let size = capital_size(settings, is_capital)
+            .unwrap_or_else(|| generate_town_size(settings, rng, &towns));
 
-        let buildings =
-            generate_buildings(settings, rng, id_tracker, &town_id, number_of_buildings);
+        let number_of_buildings = capital_buildings(
+            settings,
+            rng.gen_range(size.min_buildings..=size.max_buildings.max(size.min_buildings)),
+            is_capital,
+        );
+
+        let mut buildings = generate_buildings(
+            settings,
+            rng,
+            id_tracker,
+            &lists,
+            &grammar,
+            &town_id,
+            culture,
+            size,
+            biome,
+            number_of_buildings,
+        )?;
+        if harbour.is_port() {
+            assign_port_districts(settings, &mut buildings);
+        }
+        let layout = generate_layout(rng, &grammar, &mut buildings);
 
         towns.push(Town {
             id: town_id,
-            name: townname,
-            coords: (0, 0),
-            number_of_buildings,
+            name: raw_town.name,
+            culture: culture.map(|culture| culture.name.clone()),
+            size: TownSize(size.name.clone()),
+            population: calculate_population(size, &buildings),
+            coords,
+            biome: Biome(biome.name.clone()),
+            port: harbour.is_port(),
+            kind: TownKind::Town,
+            capital: is_capital,
+            metrics,
+            number_of_buildings: buildings.len() as u32,
             buildings,
+            layout,
         });
     }
 
     println!("done!");
 
-    towns
+    network.capital = capital.map(|index| towns[index].id);
+
+    Ok((towns, terrain, network))
 }
 
 // Generate a new graph from a raw graph and a list of towns
@@ -1045,23 +7346,133 @@ fn generate_graph_from_imported_towns(
         town_map.insert(town.name.clone(), node_idx);
     }
 
-    for edge in graph.edge_references() {
-        let (raw_source, raw_target) = (
-            graph.node_weight(edge.source()),
-            graph.node_weight(edge.target()),
-        );
+    for edge in graph.edge_references() {
+        let (raw_source, raw_target) = (
+            graph.node_weight(edge.source()),
+            graph.node_weight(edge.target()),
+        );
+
+        if let (Some(raw_source), Some(raw_target)) = (raw_source, raw_target) {
+            if let (Some(&source_idx), Some(&target_idx)) = (
+                town_map.get(&raw_source.name),
+                town_map.get(&raw_target.name),
+            ) {
+                town_graph.add_edge(source_idx, target_idx, edge.weight().clone());
+            }
+        }
+    }
+
+    town_graph
+}
+
+// Ask for the towns and options of a route to plan, returns None if the world has no towns
+fn prompt_route(
+    settings: &AppConfig,
+    graph: &Graph<Town, JourneyInfo>,
+) -> Result<Option<(NodeIndex, NodeIndex, RouteOptions)>, inquire::InquireError> {
+    // Stops along the roads and points of interest can be travelled through, but aren't offered as towns
+    let mut towns: Vec<NodeIndex> = graph
+        .node_indices()
+        .filter(|&node| graph[node].kind == TownKind::Town)
+        .collect();
+    towns.sort_by(|&a, &b| {
+        graph[a]
+            .name
+            .cmp(&graph[b].name)
+            .then(graph[a].id.cmp(&graph[b].id))
+    });
+    if towns.is_empty() {
+        println!("\nThe world has no towns.");
+        return Ok(None);
+    }
+
+    let town_names: Vec<String> = towns
+        .iter()
+        .map(|&town| format!("{} ({})", graph[town].name, graph[town].id))
+        .collect();
+    let town_for = |name: &str| {
+        towns[town_names
+            .iter()
+            .position(|town_name| town_name == name)
+            .unwrap_or_default()]
+    };
+
+    let from = town_for(&inquire::Select::new("Travel from:", town_names.clone()).prompt()?);
+    let to = town_for(&inquire::Select::new("Travel to:", town_names.clone()).prompt()?);
+
+    // Waypoints are visited in the order they're added
+    let no_waypoint = "No more waypoints".to_string();
+    let mut waypoints = Vec::new();
+    loop {
+        let choices: Vec<String> = std::iter::once(no_waypoint.clone())
+            .chain(town_names.iter().cloned())
+            .collect();
+        let choice = inquire::Select::new("Add a waypoint:", choices).prompt()?;
+        if choice == no_waypoint {
+            break;
+        }
+        waypoints.push(town_for(&choice));
+    }
+
+    let avoid_towns: Vec<NodeIndex> = inquire::MultiSelect::new("Avoid towns:", town_names.clone())
+        .prompt()?
+        .iter()
+        .map(|name| town_for(name))
+        .collect();
 
-        if let (Some(raw_source), Some(raw_target)) = (raw_source, raw_target) {
-            if let (Some(&source_idx), Some(&target_idx)) = (
-                town_map.get(&raw_source.name),
-                town_map.get(&raw_target.name),
-            ) {
-                town_graph.add_edge(source_idx, target_idx, edge.weight().clone());
-            }
+    // Towns the route has to visit can't be avoided, plan_route ignores them
+    for &town in &avoid_towns {
+        if town == from || town == to || waypoints.contains(&town) {
+            println!(
+                "{} ({}) is on the route, so it can't be avoided.",
+                graph[town].name, graph[town].id
+            );
         }
     }
 
-    town_graph
+    let road_types: Vec<String> = graph
+        .edge_weights()
+        .map(|journey_info| journey_info.road_type.clone())
+        .filter(|road_type| !road_type.is_empty())
+        .unique()
+        .collect();
+    let avoid_road_types = if road_types.is_empty() {
+        Vec::new()
+    } else {
+        inquire::MultiSelect::new("Avoid road types:", road_types).prompt()?
+    };
+
+    let metrics: Vec<String> = RouteMetric::iter()
+        .map(|metric| format!("{:?}", metric))
+        .collect();
+    let metric = inquire::Select::new("Shortest by:", metrics.clone()).prompt()?;
+    let metric = RouteMetric::iter()
+        .nth(
+            metrics
+                .iter()
+                .position(|m| *m == metric)
+                .unwrap_or_default(),
+        )
+        .unwrap_or(RouteMetric::Distance);
+
+    let any_mode = "Any".to_string();
+    let modes: Vec<String> = std::iter::once(any_mode.clone())
+        .chain(settings.travel_modes.iter().map(|mode| mode.name.clone()))
+        .collect();
+    let mode = inquire::Select::new("Travel mode:", modes).prompt()?;
+    let travel_mode = (mode != any_mode).then_some(mode);
+
+    Ok(Some((
+        from,
+        to,
+        RouteOptions {
+            metric,
+            travel_mode,
+            waypoints,
+            avoid_towns,
+            avoid_road_types,
+        },
+    )))
 }
 
 // Menu logic
@@ -1069,8 +7480,11 @@ fn menu(settings: &AppConfig) {
     let message = "Please select an option:".to_string();
     let option1 = "Generate New Towns";
     let option2 = "Import .dot file";
-    let option3 = "Exit";
-    let options = vec![option1, option2, option3];
+    let option3 = "Render town map";
+    let option4 = "Render world map";
+    let option5 = "Plan route";
+    let option6 = "Exit";
+    let options = vec![option1, option2, option3, option4, option5, option6];
 
     loop {
         println!(" ");
@@ -1079,20 +7493,53 @@ fn menu(settings: &AppConfig) {
             Ok(choice) => {
                 if choice == option1 {
                     let seed = seed_from_word(&settings.seed);
-                    let (graph, towns, world) = generate_world(settings, seed);
 
-                    match save_graph(settings, &graph, "world.dot") {
-                        Ok(result) => println!("{}", result),
-                        Err(e) => eprintln!("{}", e),
-                    }
-                    match save_towns(settings, &towns, "towns.json") {
-                        Ok(result) => println!("{}", result),
+                    match generate_world(settings, seed) {
+                        Ok((graph, towns, world)) => {
+                            match save_graph(settings, &graph, "world.dot") {
+                                Ok(result) => println!("{}", result),
+                                Err(e) => eprintln!("{}", e),
+                            }
+                            match save_svg(settings, &graph, &world.terrain, "world.svg") {
+                                Ok(result) => println!("{}", result),
+                                Err(e) => eprintln!("{}", e),
+                            }
+                            match save_png(settings, &graph, &world.terrain, "world.png") {
+                                Ok(result) => println!("{}", result),
+                                Err(e) => eprintln!("{}", e),
+                            }
+                            match save_towns(settings, &towns, "towns.json") {
+                                Ok(result) => println!("{}", result),
+                                Err(e) => eprintln!("{}", e),
+                            }
+                            match save_world(settings, &world, "world.json") {
+                                Ok(result) => println!("{}", result),
+                                Err(e) => eprint!("{}", e),
+                            }
+                            for dungeon in world.dungeons.values() {
+                                let filename = format!(
+                                    "dungeon_{}_{}.dot",
+                                    file_name_from(&dungeon.name),
+                                    dungeon.building_id
+                                );
+                                match save_dungeon(settings, dungeon, &world.rooms, &filename) {
+                                    Ok(result) => println!("{}", result),
+                                    Err(e) => eprintln!("{}", e),
+                                }
+                            }
+
+                            let matrix = generate_travel_matrix(&graph);
+                            match save_travel_matrix(settings, &matrix, "travel_matrix.json") {
+                                Ok(result) => println!("{}", result),
+                                Err(e) => eprintln!("{}", e),
+                            }
+                            match save_travel_matrix_csv(settings, &matrix, "travel_matrix.csv") {
+                                Ok(result) => println!("{}", result),
+                                Err(e) => eprintln!("{}", e),
+                            }
+                        }
                         Err(e) => eprintln!("{}", e),
                     }
-                    match save_world(settings, &world, "world.json") {
-                        Ok(result) => println!("{}", result),
-                        Err(e) => eprint!("{}", e),
-                    }
                 }
                 if choice == option2 {
                     let filename_validator = |input: &str| {
@@ -1118,6 +7565,24 @@ fn menu(settings: &AppConfig) {
                                         Ok(result) => println!("{}", result),
                                         Err(e) => eprintln!("{}", e),
                                     }
+                                    match save_svg(
+                                        settings,
+                                        &graph,
+                                        &world.terrain,
+                                        "imported_world.svg",
+                                    ) {
+                                        Ok(result) => println!("{}", result),
+                                        Err(e) => eprintln!("{}", e),
+                                    }
+                                    match save_png(
+                                        settings,
+                                        &graph,
+                                        &world.terrain,
+                                        "imported_world.png",
+                                    ) {
+                                        Ok(result) => println!("{}", result),
+                                        Err(e) => eprintln!("{}", e),
+                                    }
                                     match save_towns(settings, &towns, "imported_towns.json") {
                                         Ok(result) => println!("{}", result),
                                         Err(e) => eprintln!("{}", e),
@@ -1126,6 +7591,40 @@ fn menu(settings: &AppConfig) {
                                         Ok(result) => println!("{}", result),
                                         Err(e) => eprint!("{}", e),
                                     }
+                                    for dungeon in world.dungeons.values() {
+                                        let filename = format!(
+                                            "imported_dungeon_{}_{}.dot",
+                                            file_name_from(&dungeon.name),
+                                            dungeon.building_id
+                                        );
+                                        match save_dungeon(
+                                            settings,
+                                            dungeon,
+                                            &world.rooms,
+                                            &filename,
+                                        ) {
+                                            Ok(result) => println!("{}", result),
+                                            Err(e) => eprintln!("{}", e),
+                                        }
+                                    }
+
+                                    let matrix = generate_travel_matrix(&graph);
+                                    match save_travel_matrix(
+                                        settings,
+                                        &matrix,
+                                        "imported_travel_matrix.json",
+                                    ) {
+                                        Ok(result) => println!("{}", result),
+                                        Err(e) => eprintln!("{}", e),
+                                    }
+                                    match save_travel_matrix_csv(
+                                        settings,
+                                        &matrix,
+                                        "imported_travel_matrix.csv",
+                                    ) {
+                                        Ok(result) => println!("{}", result),
+                                        Err(e) => eprintln!("{}", e),
+                                    }
                                 }
                                 Err(e) => eprintln!("{}", e),
                             }
@@ -1134,6 +7633,108 @@ fn menu(settings: &AppConfig) {
                     }
                 }
                 if choice == option3 {
+                    match inquire::Text::new("Enter world file name to load:")
+                        .with_default("world.json")
+                        .prompt()
+                    {
+                        Ok(filename) => match load_world(settings, &filename) {
+                            Ok(world) => {
+                                let mut towns: Vec<&Town> = world.towns.values().collect();
+                                towns.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+
+                                let town_names: Vec<String> = towns
+                                    .iter()
+                                    .map(|town| format!("{} ({})", town.name, town.id))
+                                    .collect();
+
+                                match inquire::Select::new("Select a town:", town_names.clone())
+                                    .prompt()
+                                {
+                                    Ok(town_name) => {
+                                        let index = town_names
+                                            .iter()
+                                            .position(|name| *name == town_name)
+                                            .unwrap_or_default();
+                                        let town = towns[index];
+
+                                        let map = render_town_map(settings, town);
+                                        println!("\n{}", map);
+
+                                        let filename = format!(
+                                            "{}_{}_map.txt",
+                                            file_name_from(&town.name),
+                                            town.id
+                                        );
+                                        match save_map(settings, &map, &filename) {
+                                            Ok(result) => println!("{}", result),
+                                            Err(e) => eprintln!("{}", e),
+                                        }
+                                    }
+                                    Err(e) => eprintln!("{}", e),
+                                }
+                            }
+                            Err(e) => eprintln!("{}", e),
+                        },
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                if choice == option4 {
+                    match inquire::Text::new("Enter world file name to load:")
+                        .with_default("world.json")
+                        .prompt()
+                    {
+                        Ok(filename) => match load_world(settings, &filename) {
+                            Ok(world) => {
+                                let graph = graph_from_world(&world);
+                                let map =
+                                    render_world_map(settings, &graph, terminal_width(settings));
+                                println!("\n{}", map);
+
+                                match inquire::Confirm::new("Save map to file?")
+                                    .with_default(false)
+                                    .prompt()
+                                {
+                                    Ok(true) => match save_map(settings, &map, "world_map.txt") {
+                                        Ok(result) => println!("{}", result),
+                                        Err(e) => eprintln!("{}", e),
+                                    },
+                                    Ok(false) => {}
+                                    Err(e) => eprintln!("{}", e),
+                                }
+                            }
+                            Err(e) => eprintln!("{}", e),
+                        },
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                if choice == option5 {
+                    match inquire::Text::new("Enter world file name to load:")
+                        .with_default("world.json")
+                        .prompt()
+                    {
+                        Ok(filename) => match load_world(settings, &filename) {
+                            Ok(world) => {
+                                let graph = graph_from_world(&world);
+                                match prompt_route(settings, &graph) {
+                                    Ok(Some((from, to, route_options))) => {
+                                        match plan_route(&graph, from, to, &route_options) {
+                                            Some(itinerary) => println!(
+                                                "\n{}",
+                                                render_itinerary(&graph, &itinerary)
+                                            ),
+                                            None => println!("\nNo route found."),
+                                        }
+                                    }
+                                    Ok(None) => {}
+                                    Err(e) => eprintln!("{}", e),
+                                }
+                            }
+                            Err(e) => eprintln!("{}", e),
+                        },
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                if choice == option6 {
                     break;
                 }
             }
@@ -1164,3 +7765,966 @@ fn main() {
 
     println!("Goodbye!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Load the default settings, as if there were no settings file
+    fn default_settings() -> AppConfig {
+        AppConfig::load("no_settings_file").unwrap()
+    }
+
+    // Create a town with the given ID and kind, everything else left empty
+    fn town(id: u32, kind: TownKind) -> Town {
+        Town {
+            id,
+            name: format!("Town {}", id),
+            culture: None,
+            size: TownSize::default(),
+            population: 0,
+            coords: (0, 0),
+            biome: Biome::default(),
+            port: false,
+            kind,
+            capital: false,
+            metrics: TownMetrics::default(),
+            number_of_buildings: 0,
+            buildings: Vec::new(),
+            layout: TownLayout::default(),
+        }
+    }
+
+    // Create a road with a distance and cost, walked in the given hours at the same cost
+    fn road(distance: u32, cost: u32, hours: f64) -> JourneyInfo {
+        let mut journey_info = JourneyInfo::new(distance, cost, RouteKind::Road);
+        journey_info.travel = vec![TravelInfo {
+            mode: "Walking".into(),
+            hours,
+            cost,
+        }];
+        journey_info
+    }
+
+    // Find the IDs of the rooms that can be reached from a room through the rooms' exits
+    fn reachable_rooms(rooms: &[Room], from: u32) -> HashSet<u32> {
+        let mut reached = HashSet::from([from]);
+        let mut queue = std::collections::VecDeque::from([from]);
+        while let Some(room_id) = queue.pop_front() {
+            let room = rooms.iter().find(|room| room.id == room_id).unwrap();
+            for exit in &room.exits {
+                if reached.insert(exit.room_id) {
+                    queue.push_back(exit.room_id);
+                }
+            }
+        }
+        reached
+    }
+
+    // Towns A, B and C, with A-B and B-C 10 m long at 10 gold and 3 h each, and A-C 30 m long at 5 gold
+    // and 5 h
+    fn triangle() -> (Graph<Town, JourneyInfo>, [NodeIndex; 3]) {
+        let mut graph = Graph::new();
+        let a = graph.add_node(town(1, TownKind::Town));
+        let b = graph.add_node(town(2, TownKind::Town));
+        let c = graph.add_node(town(3, TownKind::Town));
+        graph.add_edge(a, b, road(10, 10, 3.0));
+        graph.add_edge(b, c, road(10, 10, 3.0));
+        graph.add_edge(a, c, road(30, 5, 5.0));
+        (graph, [a, b, c])
+    }
+
+    #[test]
+    fn path_matrix_has_shortest_values_and_next_hops() {
+        let (mut graph, _) = triangle();
+        graph.add_node(town(4, TownKind::Town));
+
+        let towns: Vec<NodeIndex> = graph.node_indices().collect();
+
+        let distance = generate_path_matrix(&graph, &towns, RouteMetric::Distance);
+        assert_eq!(
+            distance.values,
+            vec![
+                vec![Some(0.0), Some(10.0), Some(20.0), None],
+                vec![Some(10.0), Some(0.0), Some(10.0), None],
+                vec![Some(20.0), Some(10.0), Some(0.0), None],
+                vec![None, None, None, Some(0.0)],
+            ]
+        );
+        assert_eq!(
+            distance.next_hop,
+            vec![
+                vec![Some(1), Some(2), Some(2), None],
+                vec![Some(1), Some(2), Some(3), None],
+                vec![Some(2), Some(2), Some(3), None],
+                vec![None, None, None, Some(4)],
+            ]
+        );
+
+        // The direct road from A to C is cheaper, but A to B is still cheaper on its own road
+        let cost = generate_path_matrix(&graph, &towns, RouteMetric::Cost);
+        assert_eq!(cost.values[0], vec![Some(0.0), Some(10.0), Some(5.0), None]);
+        assert_eq!(cost.next_hop[0], vec![Some(1), Some(2), Some(3), None]);
+        assert_eq!(cost.values[1][2], Some(10.0));
+
+        let time = generate_path_matrix(&graph, &towns, RouteMetric::Time);
+        assert_eq!(time.values[2], vec![Some(5.0), Some(3.0), Some(0.0), None]);
+        assert_eq!(time.next_hop[2], vec![Some(1), Some(2), Some(3), None]);
+    }
+
+    #[test]
+    fn travel_matrix_passes_through_stops() {
+        // A to B is 20 m by its own road, or 10 m through the crossroads S
+        let mut graph = Graph::new();
+        let a = graph.add_node(town(1, TownKind::Town));
+        let stop = graph.add_node(town(2, TownKind::Crossroads));
+        let b = graph.add_node(town(3, TownKind::Town));
+        graph.add_edge(a, stop, road(5, 5, 1.0));
+        graph.add_edge(stop, b, road(5, 5, 1.0));
+        graph.add_edge(a, b, road(20, 5, 1.5));
+
+        let matrix = generate_travel_matrix(&graph);
+        assert_eq!(matrix.towns, vec![1, 3]);
+        assert_eq!(
+            matrix.distance.values,
+            vec![vec![Some(0.0), Some(10.0)], vec![Some(10.0), Some(0.0)]]
+        );
+        assert_eq!(
+            matrix.distance.next_hop,
+            vec![vec![Some(1), Some(3)], vec![Some(1), Some(3)]]
+        );
+        assert_eq!(matrix.cost.values[0][1], Some(5.0));
+        assert_eq!(matrix.time.values[1][0], Some(1.5));
+    }
+
+    #[test]
+    fn segments_intersect_at_their_fractions() {
+        assert_eq!(
+            segment_intersection((0.0, 0.0), (10.0, 0.0), (2.0, -2.0), (2.0, 6.0)),
+            Some((0.2, 0.25))
+        );
+        assert_eq!(
+            segment_intersection((0.0, 0.0), (10.0, 10.0), (0.0, 10.0), (10.0, 0.0)),
+            Some((0.5, 0.5))
+        );
+
+        // Parallel segments and segments that would only cross if they were longer
+        assert_eq!(
+            segment_intersection((0.0, 0.0), (10.0, 0.0), (0.0, 1.0), (10.0, 1.0)),
+            None
+        );
+        assert_eq!(
+            segment_intersection((0.0, 0.0), (10.0, 0.0), (15.0, -5.0), (15.0, 5.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn network_metrics_of_a_triangle() {
+        // A to C is shorter through B (20 m) than by its own road (30 m)
+        let (graph, _) = triangle();
+        let (metrics, network) = analyse_network(&graph);
+
+        let degrees: Vec<u32> = metrics.iter().map(|metrics| metrics.degree).collect();
+        let betweenness: Vec<f64> = metrics.iter().map(|metrics| metrics.betweenness).collect();
+        let closeness: Vec<f64> = metrics.iter().map(|metrics| metrics.closeness).collect();
+        let eccentricity: Vec<u32> = metrics.iter().map(|metrics| metrics.eccentricity).collect();
+        assert_eq!(degrees, vec![2, 2, 2]);
+        assert_eq!(betweenness, vec![0.0, 1.0, 0.0]);
+        assert_eq!(closeness, vec![0.0667, 0.1, 0.0667]);
+        assert_eq!(eccentricity, vec![20, 10, 20]);
+
+        assert_eq!(network.diameter, 20);
+        assert_eq!(network.average_path_length, 13.3);
+        assert_eq!(network.capital, None);
+    }
+
+    #[test]
+    fn network_metrics_split_betweenness_between_equal_paths() {
+        // A square of 10 m roads, opposite corners are 20 m apart by two paths
+        let mut graph = Graph::new();
+        let nodes: Vec<NodeIndex> = (1..=4)
+            .map(|id| graph.add_node(town(id, TownKind::Town)))
+            .collect();
+        for i in 0..4 {
+            graph.add_edge(nodes[i], nodes[(i + 1) % 4], road(10, 10, 3.0));
+        }
+        let (metrics, network) = analyse_network(&graph);
+
+        for metrics in &metrics {
+            assert_eq!(metrics.degree, 2);
+            assert_eq!(metrics.betweenness, 0.167);
+            assert_eq!(metrics.closeness, 0.075);
+            assert_eq!(metrics.eccentricity, 20);
+        }
+        assert_eq!(network.diameter, 20);
+        assert_eq!(network.average_path_length, 13.3);
+    }
+
+    #[test]
+    fn plan_route_by_each_metric() {
+        let (graph, [a, b, c]) = triangle();
+
+        let by_distance =
+            plan_route(&graph, a, c, &RouteOptions::new(RouteMetric::Distance)).unwrap();
+        let stops: Vec<NodeIndex> = by_distance.legs.iter().map(|leg| leg.to).collect();
+        assert_eq!(stops, vec![b, c]);
+        assert_eq!(by_distance.distance, 20);
+        assert_eq!(by_distance.cost, 20);
+        assert_eq!(by_distance.hours, Some(6.0));
+
+        let by_cost = plan_route(&graph, a, c, &RouteOptions::new(RouteMetric::Cost)).unwrap();
+        assert_eq!(by_cost.legs.len(), 1);
+        assert_eq!(by_cost.distance, 30);
+        assert_eq!(by_cost.cost, 5);
+
+        let by_time = plan_route(&graph, c, a, &RouteOptions::new(RouteMetric::Time)).unwrap();
+        assert_eq!(by_time.legs.len(), 1);
+        assert_eq!(by_time.hours, Some(5.0));
+
+        let staying = plan_route(&graph, b, b, &RouteOptions::new(RouteMetric::Time)).unwrap();
+        assert!(staying.legs.is_empty());
+        assert_eq!(staying.hours.map(f64::to_bits), Some(0.0f64.to_bits()));
+    }
+
+    #[test]
+    fn plan_route_with_waypoints_and_avoided_towns() {
+        let (graph, [a, b, c]) = triangle();
+
+        let mut options = RouteOptions::new(RouteMetric::Distance);
+        options.avoid_towns = vec![b];
+        let avoiding = plan_route(&graph, a, c, &options).unwrap();
+        assert_eq!(avoiding.distance, 30);
+
+        // A waypoint is visited even when it's also avoided
+        let mut options = RouteOptions::new(RouteMetric::Cost);
+        options.waypoints = vec![b];
+        options.avoid_towns = vec![b];
+        let via = plan_route(&graph, a, c, &options).unwrap();
+        assert_eq!(
+            via.legs.iter().map(|leg| leg.to).collect::<Vec<_>>(),
+            vec![b, c]
+        );
+        assert_eq!(via.cost, 20);
+
+        // No route when every road is of an avoided type
+        let mut graph = graph;
+        for journey_info in graph.edge_weights_mut() {
+            journey_info.road_type = "Track".into();
+        }
+        let mut options = RouteOptions::new(RouteMetric::Distance);
+        options.avoid_road_types = vec!["track".into()];
+        assert!(plan_route(&graph, a, c, &options).is_none());
+    }
+
+    #[test]
+    fn plan_route_takes_time_and_cost_from_one_mode() {
+        let mut graph = Graph::new();
+        let a = graph.add_node(town(1, TownKind::Town));
+        let b = graph.add_node(town(2, TownKind::Town));
+        let mut journey_info = road(10, 50, 4.0);
+        journey_info.travel.push(TravelInfo {
+            mode: "Riding".into(),
+            hours: 1.5,
+            cost: 8,
+        });
+        graph.add_edge(a, b, journey_info);
+
+        let any = plan_route(&graph, a, b, &RouteOptions::new(RouteMetric::Time)).unwrap();
+        assert_eq!(any.legs[0].mode.as_deref(), Some("Riding"));
+        assert_eq!(any.hours, Some(1.5));
+        assert_eq!(any.cost, 8);
+
+        let mut options = RouteOptions::new(RouteMetric::Time);
+        options.travel_mode = Some("Walking".into());
+        let walking = plan_route(&graph, b, a, &options).unwrap();
+        assert_eq!(walking.hours, Some(4.0));
+        assert_eq!(walking.cost, 50);
+    }
+
+    #[test]
+    fn plan_route_picks_the_cheapest_or_fastest_mode_by_metric() {
+        // A-B can be walked for free in 10 h or ridden for 30 gold in 2 h, A-C-B is 1 h by boat at 5 gold a leg
+        let mut graph = Graph::new();
+        let a = graph.add_node(town(1, TownKind::Town));
+        let b = graph.add_node(town(2, TownKind::Town));
+        let c = graph.add_node(town(3, TownKind::Town));
+        let mut journey_info = road(10, 0, 10.0);
+        journey_info.travel.push(TravelInfo {
+            mode: "Riding".into(),
+            hours: 2.0,
+            cost: 30,
+        });
+        graph.add_edge(a, b, journey_info);
+        for (from, to) in [(a, c), (c, b)] {
+            let mut journey_info = JourneyInfo::new(20, 5, RouteKind::River);
+            journey_info.travel = vec![TravelInfo {
+                mode: "Boat".into(),
+                hours: 0.5,
+                cost: 5,
+            }];
+            graph.add_edge(from, to, journey_info);
+        }
+
+        let by_cost = plan_route(&graph, a, b, &RouteOptions::new(RouteMetric::Cost)).unwrap();
+        assert_eq!(by_cost.legs.len(), 1);
+        assert_eq!(by_cost.legs[0].mode.as_deref(), Some("Walking"));
+        assert_eq!(by_cost.cost, 0);
+        assert_eq!(by_cost.hours, Some(10.0));
+
+        let by_time = plan_route(&graph, a, b, &RouteOptions::new(RouteMetric::Time)).unwrap();
+        assert_eq!(
+            by_time.legs.iter().map(|leg| leg.to).collect::<Vec<_>>(),
+            vec![c, b]
+        );
+        assert_eq!(by_time.cost, 10);
+        assert_eq!(by_time.hours, Some(1.0));
+
+        let towns: Vec<NodeIndex> = graph.node_indices().collect();
+        let cost = generate_path_matrix(&graph, &towns, RouteMetric::Cost);
+        assert_eq!(cost.values[0][1], Some(0.0));
+    }
+
+    #[test]
+    fn unique_names_are_numbered() {
+        let mut used_names = HashSet::from(["Oakford".to_string()]);
+
+        assert_eq!(unique_name("Ashby".into(), &mut used_names), "Ashby");
+        assert_eq!(unique_name("Oakford".into(), &mut used_names), "Oakford 2");
+        assert_eq!(unique_name("Oakford".into(), &mut used_names), "Oakford 3");
+        assert_eq!(used_names.len(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Ran out of IDs")]
+    fn ids_are_unique_until_the_range_runs_out() {
+        let mut settings = default_settings();
+        settings.min_id = 1;
+        settings.max_id = 3;
+        let mut id_tracker = IdTracker::new(0);
+
+        let mut ids: Vec<u32> = (0..3).map(|_| id_tracker.get_new_id(&settings)).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        id_tracker.get_new_id(&settings);
+    }
+
+    #[test]
+    fn dungeons_are_connected_with_one_boss_room() {
+        let settings = default_settings();
+        let mut type_config = BuildingTypeConfig::new("Dungeon");
+        type_config.dungeon = true;
+        type_config.min_rooms = Some(2);
+        type_config.max_rooms = Some(12);
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut id_tracker = IdTracker::new(seed);
+            let mut npcs = Vec::new();
+            let (rooms, floors) = generate_rooms(
+                &settings,
+                &mut rng,
+                &mut id_tracker,
+                &1,
+                &2,
+                &type_config,
+                0,
+                &mut npcs,
+            );
+            assert_eq!(floors.len(), 1);
+
+            let mut building = Building {
+                id: 2,
+                name: "Dungeon".into(),
+                building_type: BuildingType("Dungeon".into()),
+                town_id: 1,
+                coords: (0, 0),
+                district: default_district(),
+                street: String::new(),
+                address: String::new(),
+                plot: Plot::default(),
+                rooms,
+                dungeon: None,
+                floors,
+            };
+            let dungeon = generate_dungeon(&settings, &mut rng, &mut building).unwrap();
+
+            let boss_rooms: Vec<u32> = dungeon
+                .rooms
+                .iter()
+                .filter(|room| room.kind == DungeonRoomKind::BossRoom)
+                .map(|room| room.room_id)
+                .collect();
+            assert_eq!(boss_rooms, vec![dungeon.boss_room]);
+            assert_ne!(dungeon.boss_room, dungeon.entrance);
+
+            let reached = reachable_rooms(&building.rooms, dungeon.entrance);
+            assert_eq!(reached.len(), building.rooms.len());
+            assert_eq!(
+                dungeon.passages.len() * 2,
+                building
+                    .rooms
+                    .iter()
+                    .map(|room| room.exits.len())
+                    .sum::<usize>()
+            );
+        }
+    }
+
+    #[test]
+    fn every_room_can_be_reached_from_the_entrance() {
+        let mut settings = default_settings();
+        settings.door_lock_chance = 0.5;
+
+        for number_of_rooms in 1..=12 {
+            for upper_floors in 0..=3 {
+                let mut type_config = BuildingTypeConfig::new("Tavern");
+                type_config.min_rooms = Some(number_of_rooms);
+                type_config.max_rooms = Some(number_of_rooms);
+                type_config.min_upper_floors = upper_floors;
+                type_config.max_upper_floors = upper_floors;
+                type_config.cellar_chance = 1.0;
+                type_config.attic_chance = 1.0;
+
+                let seed = (number_of_rooms * 10 + upper_floors) as u64;
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut id_tracker = IdTracker::new(seed);
+                let (rooms, floors) = generate_rooms(
+                    &settings,
+                    &mut rng,
+                    &mut id_tracker,
+                    &1,
+                    &2,
+                    &type_config,
+                    0,
+                    &mut Vec::new(),
+                );
+                assert_eq!(rooms.len(), number_of_rooms as usize);
+
+                let entrances: Vec<&Room> = rooms
+                    .iter()
+                    .filter(|room| room.role == RoomRole::Entrance)
+                    .collect();
+                assert_eq!(entrances.len(), 1);
+                assert_eq!(entrances[0].floor, 0);
+
+                let reached = reachable_rooms(&rooms, entrances[0].id);
+                assert_eq!(
+                    reached.len(),
+                    rooms.len(),
+                    "{} rooms on {:?}",
+                    number_of_rooms,
+                    floors
+                );
+
+                // A building's rooms form a tree, one exit each way for every room but the entrance
+                let exits: usize = rooms.iter().map(|room| room.exits.len()).sum();
+                assert_eq!(exits, 2 * (rooms.len() - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn floors_follow_the_building_type_and_town_size() {
+        use FloorKind::*;
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Types without upper floors don't get the town size's extra floors
+        let cottage = BuildingTypeConfig::new("Cottage");
+        assert_eq!(generate_floors(&mut rng, &cottage, 2, 10), vec![Ground]);
+
+        let mut tavern = BuildingTypeConfig::new("Tavern");
+        tavern.min_upper_floors = 1;
+        tavern.max_upper_floors = 1;
+        assert_eq!(
+            generate_floors(&mut rng, &tavern, 0, 10),
+            vec![Ground, Upper]
+        );
+        assert_eq!(
+            generate_floors(&mut rng, &tavern, 2, 10),
+            vec![Ground, Upper, Upper, Upper]
+        );
+
+        tavern.cellar_chance = 1.0;
+        tavern.attic_chance = 1.0;
+        assert_eq!(
+            generate_floors(&mut rng, &tavern, 1, 10),
+            vec![Cellar, Ground, Upper, Upper, Attic]
+        );
+
+        // Every floor needs a room, small buildings lose their attic, then upper floors, then their cellar
+        assert_eq!(
+            generate_floors(&mut rng, &tavern, 1, 4),
+            vec![Cellar, Ground, Upper, Upper]
+        );
+        assert_eq!(
+            generate_floors(&mut rng, &tavern, 1, 2),
+            vec![Cellar, Ground]
+        );
+        assert_eq!(generate_floors(&mut rng, &tavern, 1, 1), vec![Ground]);
+
+        tavern.dungeon = true;
+        assert_eq!(generate_floors(&mut rng, &tavern, 1, 10), vec![Ground]);
+    }
+
+    #[test]
+    fn room_types_come_from_the_building_type() {
+        let mut tavern = BuildingTypeConfig::new("Tavern");
+        tavern.room_types = vec![
+            RoomTypeConfig {
+                min_count: 1,
+                ..RoomTypeConfig::new("Common Room", RoomRole::Entrance)
+            },
+            RoomTypeConfig {
+                min_count: 1,
+                floors: vec![FloorKind::Cellar],
+                ..RoomTypeConfig::new("Wine Cellar", RoomRole::Chamber)
+            },
+            RoomTypeConfig {
+                min_count: 1,
+                max_count: Some(1),
+                ..RoomTypeConfig::new("Kitchen", RoomRole::Chamber)
+            },
+            RoomTypeConfig {
+                floors: vec![FloorKind::Upper],
+                ..RoomTypeConfig::new("Guest Room", RoomRole::Chamber)
+            },
+        ];
+
+        let roles = [
+            (RoomRole::Entrance, FloorKind::Ground),
+            (RoomRole::Chamber, FloorKind::Ground),
+            (RoomRole::Chamber, FloorKind::Ground),
+            (RoomRole::Chamber, FloorKind::Cellar),
+            (RoomRole::Hallway, FloorKind::Upper),
+            (RoomRole::Chamber, FloorKind::Upper),
+            (RoomRole::Chamber, FloorKind::Upper),
+        ];
+        let rooms: Vec<Room> = roles
+            .iter()
+            .enumerate()
+            .map(|(id, &(role, _))| Room {
+                id: id as u32,
+                town_id: 1,
+                building_id: 2,
+                npcs: Vec::new(),
+                containers: Vec::new(),
+                purpose: String::new(),
+                floor: 0,
+                role,
+                exits: Vec::new(),
+            })
+            .collect();
+        let floors: Vec<FloorKind> = roles.iter().map(|&(_, floor)| floor).collect();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let room_types: Vec<Option<&str>> = choose_room_types(&mut rng, &tavern, &rooms, &floors)
+            .into_iter()
+            .map(|room_type| room_type.map(|room_type| room_type.name.as_str()))
+            .collect();
+
+        // The second kitchen is over its maximum and guest rooms are only upstairs, so the second ground floor
+        // chamber has no room type, and neither has the landing
+        assert_eq!(
+            room_types,
+            vec![
+                Some("Common Room"),
+                Some("Kitchen"),
+                None,
+                Some("Wine Cellar"),
+                None,
+                Some("Guest Room"),
+                Some("Guest Room"),
+            ]
+        );
+    }
+
+    // Create a constraint on a building type that doesn't limit it yet
+    fn constraint(building_type: &str) -> ConstraintConfig {
+        ConstraintConfig {
+            building_type: building_type.into(),
+            min: None,
+            max: None,
+            per_buildings: None,
+            requires: None,
+            town_sizes: Vec::new(),
+        }
+    }
+
+    // Settings with a residence, tavern, temple and guard barracks, and the given constraints
+    fn constrained_settings(constraints: Vec<ConstraintConfig>) -> AppConfig {
+        let mut settings = default_settings();
+        settings.building_types = ["Residence", "Tavern", "Temple", "Guard Barracks"]
+            .iter()
+            .map(|name| BuildingTypeConfig::new(name))
+            .collect();
+        settings.building_types[1].max_count = Some(2);
+        settings.constraints = constraints;
+        settings
+    }
+
+    // Count the buildings of each type, in the order of the settings' building types
+    fn type_counts(settings: &AppConfig, building_types: &[&BuildingTypeConfig]) -> Vec<usize> {
+        settings
+            .building_types
+            .iter()
+            .map(|type_config| {
+                building_types
+                    .iter()
+                    .filter(|building_type| building_type.name == type_config.name)
+                    .count()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn building_types_satisfy_the_constraints() {
+        let settings = constrained_settings(vec![
+            ConstraintConfig {
+                min: Some(1),
+                ..constraint("Tavern")
+            },
+            ConstraintConfig {
+                max: Some(1),
+                per_buildings: Some(10),
+                ..constraint("Temple")
+            },
+            ConstraintConfig {
+                max: Some(0),
+                town_sizes: vec!["Village".into()],
+                ..constraint("Guard Barracks")
+            },
+        ]);
+        let mut town_size = TownSizeConfig::new("Village", 1.0, 20, 20);
+        town_size.services.insert("Temple".into(), 1);
+        let biome = BiomeConfig::new("Grassland", (0.0, 1.0), (0.0, 1.0), [0, 0, 0]);
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let building_types =
+                generate_building_types(&settings, &mut rng, &town_size, &biome, 20).unwrap();
+            let counts = type_counts(&settings, &building_types);
+
+            assert_eq!(building_types.len(), 20);
+            assert!((1..=2).contains(&counts[1]), "{:?}", counts);
+            assert!((1..=2).contains(&counts[2]), "{:?}", counts);
+            assert_eq!(counts[3], 0);
+        }
+    }
+
+    #[test]
+    fn unsatisfiable_constraints_are_errors() {
+        let town_size = TownSizeConfig::new("Village", 1.0, 5, 5);
+        let biome = BiomeConfig::new("Grassland", (0.0, 1.0), (0.0, 1.0), [0, 0, 0]);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // More taverns than the tavern's maximum count
+        let settings = constrained_settings(vec![ConstraintConfig {
+            min: Some(3),
+            ..constraint("Tavern")
+        }]);
+        let error =
+            generate_building_types(&settings, &mut rng, &town_size, &biome, 5).unwrap_err();
+        assert!(
+            error.to_string().contains("needs at least 3 Tavern"),
+            "{}",
+            error
+        );
+
+        // More required buildings than the town has
+        let settings = constrained_settings(vec![ConstraintConfig {
+            min: Some(6),
+            ..constraint("Temple")
+        }]);
+        let error =
+            generate_building_types(&settings, &mut rng, &town_size, &biome, 5).unwrap_err();
+        assert!(
+            error.to_string().contains("can't fit the 6 buildings"),
+            "{}",
+            error
+        );
+
+        // Every building type is full before the town is, at most 5 buildings fit in 6
+        let mut settings = constrained_settings(
+            ["Residence", "Temple", "Guard Barracks"]
+                .iter()
+                .map(|name| ConstraintConfig {
+                    max: Some(1),
+                    ..constraint(name)
+                })
+                .collect(),
+        );
+        let error =
+            generate_building_types(&settings, &mut rng, &town_size, &biome, 6).unwrap_err();
+        assert!(
+            error.to_string().contains("only 5 of the 6 buildings"),
+            "{}",
+            error
+        );
+
+        // A constraint on a building type that doesn't exist
+        settings.constraints = vec![constraint("Castle")];
+        let error =
+            generate_building_types(&settings, &mut rng, &town_size, &biome, 5).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("unknown building type \"Castle\""),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn required_buildings_are_added() {
+        // Chains of requirements are followed, a tavern needs guard barracks, which need a temple
+        assert_eq!(
+            add_required_buildings(&[0, 3, 0, 0], &[(1, 3), (3, 2)]),
+            vec![0, 3, 3, 3]
+        );
+        assert_eq!(
+            add_required_buildings(&[0, 1, 4, 0], &[(1, 2)]),
+            vec![0, 1, 4, 0]
+        );
+
+        let settings = constrained_settings(vec![
+            ConstraintConfig {
+                min: Some(2),
+                requires: Some("Guard Barracks".into()),
+                ..constraint("Tavern")
+            },
+            ConstraintConfig {
+                requires: Some("temple".into()),
+                ..constraint("Guard Barracks")
+            },
+        ]);
+        let town_size = TownSizeConfig::new("Town", 1.0, 10, 10);
+        let biome = BiomeConfig::new("Grassland", (0.0, 1.0), (0.0, 1.0), [0, 0, 0]);
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let building_types =
+                generate_building_types(&settings, &mut rng, &town_size, &biome, 10).unwrap();
+            let counts = type_counts(&settings, &building_types);
+
+            assert_eq!(counts[1], 2);
+            assert!(counts[3] >= counts[1], "{:?}", counts);
+            assert!(counts[2] >= counts[3], "{:?}", counts);
+        }
+    }
+
+    // Parse the lines of a list, skipping comments and invalid lines
+    fn list(lines: &[&str]) -> Vec<ListEntry> {
+        lines
+            .iter()
+            .filter_map(|line| ListEntry::from_line(line))
+            .collect()
+    }
+
+    #[test]
+    fn list_entries_are_parsed_with_weights_and_tags() {
+        let entry = ListEntry::from_line("  Aldric | 5 | Human, Male ").unwrap();
+        assert_eq!(entry.value, "Aldric");
+        assert_eq!(entry.weight, 5);
+        assert_eq!(entry.tags, vec!["human", "male"]);
+
+        let entry = ListEntry::from_line("Bran").unwrap();
+        assert_eq!((entry.weight, entry.tags.len()), (1, 0));
+
+        let entry = ListEntry::from_line("Elrond | | elf,").unwrap();
+        assert_eq!(entry.weight, 1);
+        assert_eq!(entry.tags, vec!["elf"]);
+
+        // Comments, blank lines, entries without a value and weights that aren't whole numbers
+        for line in ["# Comment", "   ", "| 3 | elf", "Cara | 2.5", "Dain | -1"] {
+            assert!(ListEntry::from_line(line).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn list_entries_are_chosen_by_tags() {
+        let entries = list(&["Aldric | 1 | human", "Elrond | 1 | elf", "Bran"]);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..20 {
+            let name = choose_entry(&mut rng, &entries, &["elf".into()]).unwrap();
+            assert!(name == "Elrond" || name == "Bran", "{}", name);
+            let name = choose_entry(&mut rng, &entries, &["dwarf".into()]).unwrap();
+            assert_eq!(name, "Bran");
+        }
+
+        // Tagged entries are never used for the wrong tags, even when nothing else is left
+        let tagged = list(&["Aldric | 1 | human", "Elrond | 1 | elf, male"]);
+        assert_eq!(choose_entry(&mut rng, &tagged, &["dwarf".into()]), None);
+        assert_eq!(choose_entry(&mut rng, &tagged, &["elf".into()]), None);
+        assert_eq!(
+            choose_entry(&mut rng, &tagged, &["male".into(), "elf".into()]),
+            Some("Elrond".into())
+        );
+
+        // Entries that all have a weight of 0 are picked evenly
+        let unweighted = list(&["Cara | 0", "Dain | 0"]);
+        assert!(choose_entry(&mut rng, &unweighted, &[]).is_some());
+        assert_eq!(choose_entry(&mut rng, &[], &[]), None);
+    }
+
+    // Train a Markov chain on a list with the given name lengths
+    fn markov(
+        entries: &[ListEntry],
+        lengths: (usize, usize),
+        allow_training_names: bool,
+    ) -> MarkovModel {
+        let mut settings = default_settings();
+        settings.markov_order = 2;
+        settings.markov_min_length = lengths.0;
+        settings.markov_max_length = lengths.1;
+        settings.markov_allow_training_names = allow_training_names;
+
+        let training_names = entries
+            .iter()
+            .map(|entry| entry.value.to_lowercase())
+            .collect();
+        MarkovModel::train(
+            &settings,
+            &entries.iter().collect::<Vec<_>>(),
+            training_names,
+        )
+    }
+
+    #[test]
+    fn markov_names_reject_training_names_unless_allowed() {
+        // A chain trained on one name can only generate that name
+        let entries = list(&["Thornbury | 3"]);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert_eq!(markov(&entries, (3, 12), false).generate(&mut rng), None);
+        let model = markov(&entries, (3, 12), true);
+        assert_eq!(model.weight, 3);
+        assert_eq!(model.generate(&mut rng), Some("Thornbury".into()));
+    }
+
+    #[test]
+    fn markov_names_keep_to_the_length_limits() {
+        let entries = list(&["Thornbury"]);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Names are cut off at the maximum length, and shorter names than the minimum are rejected
+        assert_eq!(
+            markov(&entries, (3, 5), true).generate(&mut rng),
+            Some("Thorn".into())
+        );
+        assert_eq!(markov(&entries, (10, 12), true).generate(&mut rng), None);
+        // A maximum below the minimum is raised to it
+        assert_eq!(
+            markov(&entries, (4, 2), true).generate(&mut rng),
+            Some("Thor".into())
+        );
+    }
+
+    #[test]
+    fn markov_chains_are_picked_by_tags() {
+        let entries = list(&["Aldric | 1 | human", "Elrond | 1 | elf"]);
+        let source = NameSource {
+            markov: entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.tags.clone(),
+                        markov(std::slice::from_ref(entry), (3, 12), true),
+                    )
+                })
+                .collect(),
+            entries,
+            phonotactics: None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..10 {
+            assert_eq!(
+                source.choose(&mut rng, &["elf".into()]),
+                Some("Elrond".into())
+            );
+            assert_eq!(
+                source.choose(&mut rng, &["human".into()]),
+                Some("Aldric".into())
+            );
+        }
+        // Without a chain for the tags the list is used, which has nothing untagged to fall back to
+        assert_eq!(source.choose(&mut rng, &["dwarf".into()]), None);
+    }
+
+    #[test]
+    fn grammar_modifiers() {
+        assert_eq!(apply_modifier("ash grove", "capitalize"), "Ash grove");
+        assert_eq!(apply_modifier("ash grove", "title"), "Ash Grove");
+        assert_eq!(apply_modifier("Inn", "upper"), "INN");
+        assert_eq!(apply_modifier("Inn", "lower"), "inn");
+        assert_eq!(apply_modifier("", "capitalize"), "");
+
+        for (text, plural) in [
+            ("bell", "bells"),
+            ("fox", "foxes"),
+            ("church", "churches"),
+            ("city", "cities"),
+            ("day", "days"),
+            ("Moss", "Mosses"),
+        ] {
+            assert_eq!(apply_modifier(text, "plural"), plural);
+            assert_eq!(apply_modifier(text, "s"), plural);
+        }
+
+        assert_eq!(apply_modifier("Miller", "possessive"), "Miller's");
+        assert_eq!(apply_modifier("Jones", "possessive"), "Jones'");
+        assert_eq!(apply_modifier("inn", "a"), "an inn");
+        assert_eq!(apply_modifier("tavern", "a"), "a tavern");
+        assert_eq!(apply_modifier("tavern", "unknown"), "tavern");
+    }
+
+    #[test]
+    fn grammar_expands_nested_rules_and_symbols() {
+        let grammar = Grammar {
+            rules: HashMap::from([
+                ("town".into(), vec!["#root.capitalize# #place.s#".into()]),
+                ("place".into(), vec!["#kind#".into()]),
+                ("kind".into(), vec!["ferry".into()]),
+                (
+                    "sign".into(),
+                    vec!["The #owner.possessive# #kind.title#".into()],
+                ),
+                ("loop".into(), vec!["#loop#".into()]),
+                ("root".into(), vec!["elm".into()]),
+            ]),
+        };
+        let entries = list(&["jones"]);
+        let symbols = HashMap::from([
+            ("root", GrammarSymbol::Text("oak".into())),
+            ("owner", GrammarSymbol::List(&entries)),
+        ]);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert_eq!(
+            grammar.generate(&mut rng, "town", &symbols, &[]),
+            "Oak ferries"
+        );
+        assert_eq!(
+            grammar.generate(&mut rng, "sign", &symbols, &[]),
+            "The jones' Ferry"
+        );
+
+        // Bound symbols take priority over rules, and unknown or endlessly recursive symbols give NO DATA
+        let template = "#kind# #root# #missing# #loop#";
+        assert_eq!(
+            grammar.generate_from_template(&mut rng, template, &symbols, &[]),
+            "ferry oak NO DATA NO DATA"
+        );
+
+        // A # that isn't closed is kept as text
+        assert!(Grammar::is_balanced("#root# #kind.s#"));
+        assert!(!Grammar::is_balanced("#root# #kind"));
+        assert_eq!(
+            grammar.generate_from_template(&mut rng, "#root# #kind", &symbols, &[]),
+            "oak #kind"
+        );
+    }
+}